@@ -0,0 +1,19 @@
+LDC 1 42
+LDC 2 4
+ADDI 3 31 44
+ADDI 4 31 1
+SW 3 4
+FENCE
+LL 1 5
+ADDI 5 5 1
+SC 1 5 6
+BEQZ 6 6
+LW 1 5
+BLT 5 2 10
+ADDI 7 31 1
+MOD 7 7 2
+ADDI 7 7 44
+LW 7 8
+ADDI 9 31 48
+SW 9 8
+NOOP
\ No newline at end of file
@@ -0,0 +1,10 @@
+ADDI 1 31 40
+LDC 2 0
+LDC 3 16
+BEQ 2 3 9
+LW 1 4
+ADDI 4 4 1
+SW 1 4
+ADDI 2 2 1
+J 3
+NOOP
\ No newline at end of file
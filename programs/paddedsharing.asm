@@ -0,0 +1,12 @@
+LDC 5 4
+MULT 1 31 5
+ADDI 1 1 32
+LDC 2 0
+LDC 3 16
+BEQ 2 3 11
+LW 1 4
+ADDI 4 4 1
+SW 1 4
+ADDI 2 2 1
+J 5
+NOOP
\ No newline at end of file
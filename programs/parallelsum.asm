@@ -0,0 +1,12 @@
+LDC 1 8
+MULT 2 31 1
+ADD 3 2 1
+LDC 4 0
+BEQ 2 3 9
+LW 2 5
+ADD 4 4 5
+ADDI 2 2 1
+J 4
+ADDI 6 31 40
+SW 6 4
+NOOP
\ No newline at end of file
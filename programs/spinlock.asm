@@ -0,0 +1,19 @@
+LDC 1 40
+LDC 2 41
+LDC 3 0
+LDC 4 10
+LDC 6 0
+LDC 7 1
+BEQ 3 4 18
+LL 1 5
+BGT 5 6 7
+SC 1 7 8
+BEQZ 8 7
+LW 2 9
+ADDI 9 9 1
+SW 2 9
+FENCE
+SW 1 6
+ADDI 3 3 1
+J 6
+NOOP
\ No newline at end of file
@@ -0,0 +1,297 @@
+use rand;
+use rand::Rng;
+use stats::Stats;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ReplacementPolicy {
+    Lru,
+    Plru,
+    Random,
+    Fifo,
+}
+
+impl ReplacementPolicy {
+    pub fn parse(name: &str) -> ReplacementPolicy {
+        match name.to_lowercase().as_str() {
+            "lru" => ReplacementPolicy::Lru,
+            "plru" => ReplacementPolicy::Plru,
+            "random" => ReplacementPolicy::Random,
+            "fifo" => ReplacementPolicy::Fifo,
+            _ => panic!("Unaccepted replacement policy {}", name),
+        }
+    }
+}
+
+// Sizes are in words as memory is word addressed
+#[derive(Debug, Copy, Clone)]
+pub struct CacheConfig {
+    pub size: usize,
+    pub line_size: usize,
+    pub assoc: usize,
+    pub hit_latency: u32,
+    pub replacement: ReplacementPolicy,
+    pub write_back: bool,
+    pub write_allocate: bool,
+}
+
+impl CacheConfig {
+    pub fn num_sets(&self) -> usize {
+        self.size / (self.line_size * self.assoc)
+    }
+
+    fn validate(&self) {
+        if self.line_size == 0 || self.assoc == 0 || self.size == 0 {
+            panic!("Cache size, line size and associativity must be non zero {:?}", self);
+        }
+        if !self.size.is_multiple_of(self.line_size * self.assoc) {
+            panic!("Cache size must be a multiple of line size * associativity {:?}", self);
+        }
+        if self.replacement == ReplacementPolicy::Plru && !self.assoc.is_power_of_two() {
+            panic!("PLRU replacement needs a power of two associativity {:?}", self);
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+struct CacheLine {
+    valid: bool,
+    dirty: bool,
+    tag: usize,
+    last_used: u64,
+    inserted: u64,
+}
+
+impl CacheLine {
+    fn new() -> CacheLine {
+        CacheLine {
+            valid: false,
+            dirty: false,
+            tag: 0,
+            last_used: 0,
+            inserted: 0,
+        }
+    }
+}
+
+// A line leaving a cache, addressed by the word address of its first word
+#[derive(Debug, Copy, Clone)]
+pub struct Victim {
+    pub addr: usize,
+    pub dirty: bool,
+}
+
+#[derive(Debug, Default, Copy, Clone)]
+pub struct CacheStats {
+    pub reads: u64,
+    pub writes: u64,
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    pub writebacks: u64,
+}
+
+impl CacheStats {
+    // 0 rather than NaN for a cache that was never accessed
+    pub fn hit_rate(&self) -> f32 {
+        let accesses = self.hits + self.misses;
+        if accesses == 0 { 0.0 } else { self.hits as f32 / accesses as f32 }
+    }
+
+    pub fn record(&self, stats: &mut Stats) {
+        stats.counter("reads", self.reads);
+        stats.counter("writes", self.writes);
+        stats.counter("hits", self.hits);
+        stats.counter("misses", self.misses);
+        stats.counter("evictions", self.evictions);
+        stats.counter("writebacks", self.writebacks);
+    }
+}
+
+#[derive(Debug)]
+pub struct Cache {
+    pub config: CacheConfig,
+    sets: Vec<Vec<CacheLine>>,
+    plru: Vec<Vec<bool>>,
+    accesses: u64,
+    pub stats: CacheStats,
+}
+
+impl Cache {
+    pub fn new(config: CacheConfig) -> Cache {
+        config.validate();
+        let num_sets = config.num_sets();
+        Cache {
+            config,
+            sets: vec![vec![CacheLine::new(); config.assoc]; num_sets],
+            plru: vec![vec![false; config.assoc - 1]; num_sets],
+            accesses: 0,
+            stats: CacheStats::default(),
+        }
+    }
+
+    pub fn line_addr(&self, addr: usize) -> usize {
+        addr / self.config.line_size
+    }
+
+    fn set_index(&self, line: usize) -> usize {
+        line % self.sets.len()
+    }
+
+    fn tag(&self, line: usize) -> usize {
+        line / self.sets.len()
+    }
+
+    fn find(&self, addr: usize) -> Option<(usize, usize)> {
+        let line = self.line_addr(addr);
+        let set = self.set_index(line);
+        let tag = self.tag(line);
+        for way in 0..self.sets[set].len() {
+            let l = &self.sets[set][way];
+            if l.valid && l.tag == tag {
+                return Some((set, way));
+            }
+        }
+        None
+    }
+
+    fn count(&mut self, write: bool) {
+        self.accesses += 1;
+        if write {
+            self.stats.writes += 1;
+        } else {
+            self.stats.reads += 1;
+        }
+    }
+
+    // Checks for a line without touching replacement state or statistics
+    pub fn probe(&self, addr: usize) -> bool {
+        self.find(addr).is_some()
+    }
+
+    // A secondary miss to a line that is already being filled
+    pub fn merge(&mut self, addr: usize, write: bool) {
+        self.count(write);
+        self.stats.misses += 1;
+        if let Some((set, way)) = self.find(addr) {
+            self.touch(set, way);
+            if write && self.config.write_back {
+                self.sets[set][way].dirty = true;
+            }
+        }
+    }
+
+    // Counts an access and updates the line on a hit but never allocates
+    pub fn lookup(&mut self, addr: usize, write: bool) -> bool {
+        self.count(write);
+        if let Some((set, way)) = self.find(addr) {
+            self.stats.hits += 1;
+            self.touch(set, way);
+            if write && self.config.write_back {
+                self.sets[set][way].dirty = true;
+            }
+            true
+        } else {
+            self.stats.misses += 1;
+            false
+        }
+    }
+
+    // Brings the line holding addr into the cache without counting as an access
+    pub fn insert(&mut self, addr: usize, dirty: bool) -> Option<Victim> {
+        if let Some((set, way)) = self.find(addr) {
+            self.sets[set][way].dirty |= dirty;
+            return None;
+        }
+
+        let line = self.line_addr(addr);
+        let set = self.set_index(line);
+        let way = self.victim(set);
+
+        let mut evicted = None;
+        let old = self.sets[set][way];
+        if old.valid {
+            self.stats.evictions += 1;
+            if old.dirty {
+                self.stats.writebacks += 1;
+            }
+            let old_line = old.tag * self.sets.len() + set;
+            evicted = Some(Victim { addr: old_line * self.config.line_size, dirty: old.dirty });
+        }
+
+        self.sets[set][way] = CacheLine {
+            valid: true,
+            dirty,
+            tag: self.tag(line),
+            last_used: self.accesses,
+            inserted: self.accesses,
+        };
+        self.touch(set, way);
+        evicted
+    }
+
+    // Removes the line holding addr, returning whether it was dirty if it was present
+    pub fn invalidate(&mut self, addr: usize) -> Option<bool> {
+        if let Some((set, way)) = self.find(addr) {
+            let dirty = self.sets[set][way].dirty;
+            self.sets[set][way] = CacheLine::new();
+            Some(dirty)
+        } else {
+            None
+        }
+    }
+
+    // Marks the line holding addr clean once written back, returning whether it was dirty
+    pub fn clean(&mut self, addr: usize) -> bool {
+        match self.find(addr) {
+            Some((set, way)) => {
+                let dirty = self.sets[set][way].dirty;
+                self.sets[set][way].dirty = false;
+                dirty
+            },
+            None => false,
+        }
+    }
+
+    fn touch(&mut self, set: usize, way: usize) {
+        self.sets[set][way].last_used = self.accesses;
+        if self.config.replacement == ReplacementPolicy::Plru {
+            // Point every node on the path away from the way just used
+            let levels = self.config.assoc.trailing_zeros();
+            let mut node = 0;
+            for level in (0..levels).rev() {
+                let bit = (way >> level) & 1;
+                self.plru[set][node] = bit == 0;
+                node = 2 * node + 1 + bit;
+            }
+        }
+    }
+
+    fn victim(&self, set: usize) -> usize {
+        if let Some(way) = self.sets[set].iter().position(|l| !l.valid) {
+            return way;
+        }
+        let ways = &self.sets[set];
+        match self.config.replacement {
+            ReplacementPolicy::Lru => {
+                (0..ways.len()).min_by_key(|&w| ways[w].last_used).unwrap()
+            },
+            ReplacementPolicy::Fifo => {
+                (0..ways.len()).min_by_key(|&w| ways[w].inserted).unwrap()
+            },
+            ReplacementPolicy::Random => {
+                rand::thread_rng().gen_range(0, ways.len())
+            },
+            ReplacementPolicy::Plru => {
+                let levels = self.config.assoc.trailing_zeros();
+                let mut node = 0;
+                let mut way = 0;
+                for _ in 0..levels {
+                    let dir = self.plru[set][node] as usize;
+                    way = (way << 1) | dir;
+                    node = 2 * node + 1 + dir;
+                }
+                way
+            },
+        }
+    }
+}
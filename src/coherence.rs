@@ -0,0 +1,199 @@
+use std::collections::HashMap;
+use stats::Stats;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Protocol {
+    None,
+    Mesi,
+    Moesi,
+}
+
+impl Protocol {
+    pub fn parse(name: &str) -> Protocol {
+        match name.to_lowercase().as_str() {
+            "none" => Protocol::None,
+            "mesi" => Protocol::Mesi,
+            "moesi" => Protocol::Moesi,
+            _ => panic!("Unaccepted coherence protocol {}", name),
+        }
+    }
+
+    // The name --coherence takes
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Protocol::None => "none",
+            Protocol::Mesi => "mesi",
+            Protocol::Moesi => "moesi",
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum LineState {
+    M,
+    O,
+    E,
+    S,
+    I,
+}
+
+impl LineState {
+    // Lines holding data memory does not have yet
+    pub fn owned(&self) -> bool {
+        *self == LineState::M || *self == LineState::O
+    }
+}
+
+// Transactions on the snooping bus, BusWr being a write that does not allocate in the writer's cache
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum BusOp {
+    BusRd,
+    BusRdX,
+    BusUpgr,
+    BusWr,
+}
+
+// How a cache holding a line reacts to another core's transaction
+#[derive(Debug, Copy, Clone)]
+pub struct Snooped {
+    pub next: LineState,
+    // Sends its copy of the line straight to the requesting cache
+    pub supplies: bool,
+    // Writes its dirty copy back to the next level
+    pub flushes: bool,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct CoherenceConfig {
+    pub protocol: Protocol,
+    // Cycles to win the bus and have every other cache snoop the transaction
+    pub snoop_latency: u32,
+    // Cycles for a line to move from one L1 data cache to another
+    pub transfer_latency: u32,
+    pub trace: bool,
+}
+
+#[derive(Debug, Default, Copy, Clone)]
+pub struct CoherenceStats {
+    pub bus_reads: u64,
+    pub bus_read_exclusives: u64,
+    pub upgrades: u64,
+    pub bus_writes: u64,
+    // Writes to exclusive lines that needed no bus transaction
+    pub silent_upgrades: u64,
+    pub cache_to_cache: u64,
+    pub flushes: u64,
+    pub invalidations: u64,
+}
+
+impl CoherenceStats {
+    // Requests on the bus plus the data responses and write backs they cause
+    pub fn messages(&self) -> u64 {
+        self.bus_reads + self.bus_read_exclusives + self.upgrades + self.bus_writes + self.cache_to_cache + self.flushes
+    }
+
+    pub fn record(&self, stats: &mut Stats) {
+        stats.counter("bus_reads", self.bus_reads);
+        stats.counter("bus_read_exclusives", self.bus_read_exclusives);
+        stats.counter("upgrades", self.upgrades);
+        stats.counter("bus_writes", self.bus_writes);
+        stats.counter("silent_upgrades", self.silent_upgrades);
+        stats.counter("cache_to_cache", self.cache_to_cache);
+        stats.counter("flushes", self.flushes);
+        stats.counter("invalidations", self.invalidations);
+    }
+}
+
+// Line states of every core's L1 data cache, a line the cache no longer holds being invalid
+#[derive(Debug)]
+pub struct Coherence {
+    pub config: CoherenceConfig,
+    states: Vec<HashMap<usize, LineState>>,
+    pub stats: CoherenceStats,
+    // Invalidations each core's cache received
+    pub invalidations_received: Vec<u64>,
+    // Invalidations of each line, where lines moving back and forth between cores show up
+    pub invalidations_by_line: HashMap<usize, u64>,
+}
+
+impl Coherence {
+    pub fn new(config: CoherenceConfig, cores: usize) -> Coherence {
+        Coherence {
+            config,
+            states: vec![HashMap::new(); cores],
+            stats: CoherenceStats::default(),
+            invalidations_received: vec![0; cores],
+            invalidations_by_line: HashMap::new(),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.config.protocol != Protocol::None
+    }
+
+    // The state of a line the cache holds, lines filled before any state was recorded being treated as shared
+    pub fn state(&self, core: usize, line: usize, present: bool) -> LineState {
+        if !present {
+            return LineState::I;
+        }
+        self.states[core].get(&line).cloned().unwrap_or(LineState::S)
+    }
+
+    pub fn set(&mut self, core: usize, line: usize, from: LineState, to: LineState, cause: &str, cycle: u64) {
+        if self.config.trace && from != to {
+            println!("Coherence cycle {}: core {} line {} {:?} -> {:?} ({})", cycle, core, line, from, to, cause);
+        }
+        if to == LineState::I {
+            self.states[core].remove(&line);
+            if from != LineState::I {
+                self.stats.invalidations += 1;
+                self.invalidations_received[core] += 1;
+                *self.invalidations_by_line.entry(line).or_insert(0) += 1;
+            }
+        } else {
+            self.states[core].insert(line, to);
+        }
+    }
+
+    // The transaction a core needs before it can read or write a line in the given state
+    pub fn request(&mut self, state: LineState, write: bool, allocates: bool) -> Option<BusOp> {
+        let op = match (write, state) {
+            (false, LineState::I) => Some(BusOp::BusRd),
+            (false, _) => None,
+            (true, LineState::I) if !allocates => Some(BusOp::BusWr),
+            (true, LineState::I) => Some(BusOp::BusRdX),
+            (true, LineState::S) | (true, LineState::O) => Some(BusOp::BusUpgr),
+            (true, LineState::E) => {
+                self.stats.silent_upgrades += 1;
+                None
+            },
+            (true, LineState::M) => None,
+        };
+        match op {
+            Some(BusOp::BusRd) => self.stats.bus_reads += 1,
+            Some(BusOp::BusRdX) => self.stats.bus_read_exclusives += 1,
+            Some(BusOp::BusUpgr) => self.stats.upgrades += 1,
+            Some(BusOp::BusWr) => self.stats.bus_writes += 1,
+            None => (),
+        }
+        op
+    }
+
+    pub fn snoop(&self, state: LineState, op: BusOp) -> Snooped {
+        let moesi = self.config.protocol == Protocol::Moesi;
+        match op {
+            BusOp::BusRd => match state {
+                // Under MESI the dirty line goes back to memory as it becomes shared, MOESI keeps it as the owner
+                LineState::M if moesi => Snooped { next: LineState::O, supplies: true, flushes: false },
+                LineState::M => Snooped { next: LineState::S, supplies: true, flushes: true },
+                LineState::O => Snooped { next: LineState::O, supplies: true, flushes: false },
+                _ => Snooped { next: LineState::S, supplies: false, flushes: false },
+            },
+            // The requester takes over the dirty data along with the line
+            BusOp::BusRdX => Snooped { next: LineState::I, supplies: state.owned(), flushes: false },
+            // The upgrading cache already has the owner's data so only needs the other copies gone
+            BusOp::BusUpgr => Snooped { next: LineState::I, supplies: false, flushes: state == LineState::M },
+            BusOp::BusWr => Snooped { next: LineState::I, supplies: false, flushes: state.owned() },
+        }
+    }
+}
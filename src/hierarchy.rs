@@ -0,0 +1,692 @@
+use cache::{Cache, CacheConfig, Victim};
+use prefetch::{Prefetcher, PrefetcherKind};
+use coherence::{BusOp, Coherence, CoherenceConfig, LineState};
+use stats::{self, Stats};
+
+// Instructions live in their own region of the address space so they do not alias data in shared levels
+const INSTRUCTION_BASE: usize = 1 << 16;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Inclusion {
+    Inclusive,
+    Exclusive,
+    NonInclusive,
+}
+
+impl Inclusion {
+    pub fn parse(name: &str) -> Inclusion {
+        match name.to_lowercase().as_str() {
+            "inclusive" => Inclusion::Inclusive,
+            "exclusive" => Inclusion::Exclusive,
+            "nine" => Inclusion::NonInclusive,
+            _ => panic!("Unaccepted inclusion policy {}", name),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct DramConfig {
+    pub row_hit_latency: u32,
+    pub row_miss_latency: u32,
+    pub banks: usize,
+    // Words per row in each bank
+    pub row_size: usize,
+    // Words transferred over the data bus per cycle
+    pub bandwidth: usize,
+}
+
+// Everything about the memory system besides the number of cores sharing it
+#[derive(Debug, Clone)]
+pub struct MemoryConfig {
+    pub l1i: CacheConfig,
+    pub l1d: CacheConfig,
+    // The shared levels, None when disabled
+    pub l2: Option<CacheConfig>,
+    pub l3: Option<CacheConfig>,
+    pub inclusion: Inclusion,
+    pub dram: DramConfig,
+    // Miss status holding registers in front of each L1 data cache
+    pub mshrs: usize,
+    pub prefetcher: Prefetcher,
+    pub coherence: CoherenceConfig,
+}
+
+#[derive(Debug, Default, Copy, Clone)]
+pub struct DramStats {
+    pub reads: u64,
+    pub writes: u64,
+    pub row_hits: u64,
+    pub row_misses: u64,
+    pub bank_stall_cycles: u64,
+    pub bus_stall_cycles: u64,
+}
+
+impl DramStats {
+    // 0 rather than NaN when nothing reached DRAM
+    pub fn row_hit_rate(&self) -> f32 {
+        let accesses = self.row_hits + self.row_misses;
+        if accesses == 0 { 0.0 } else { self.row_hits as f32 / accesses as f32 }
+    }
+
+    pub fn record(&self, stats: &mut Stats) {
+        stats.counter("reads", self.reads);
+        stats.counter("writes", self.writes);
+        stats.counter("row_hits", self.row_hits);
+        stats.counter("row_misses", self.row_misses);
+        stats.counter("bank_stall_cycles", self.bank_stall_cycles);
+        stats.counter("bus_stall_cycles", self.bus_stall_cycles);
+    }
+}
+
+// Open page DRAM with per bank row buffers sharing one data bus
+#[derive(Debug)]
+pub struct Dram {
+    config: DramConfig,
+    open_rows: Vec<Option<usize>>,
+    bank_free: Vec<u64>,
+    bus_free: u64,
+    pub stats: DramStats,
+}
+
+impl Dram {
+    fn new(config: DramConfig) -> Dram {
+        if config.banks == 0 || config.row_size == 0 || config.bandwidth == 0 {
+            panic!("DRAM banks, row size and bandwidth must be non zero {:?}", config);
+        }
+        Dram {
+            config,
+            open_rows: vec![None; config.banks],
+            bank_free: vec![0; config.banks],
+            bus_free: 0,
+            stats: DramStats::default(),
+        }
+    }
+
+    // Transfers words starting at addr, returning the cycles until the last word arrives
+    fn access(&mut self, now: u64, addr: usize, words: usize, write: bool) -> u32 {
+        if write {
+            self.stats.writes += 1;
+        } else {
+            self.stats.reads += 1;
+        }
+
+        let bank = (addr / self.config.row_size) % self.config.banks;
+        let row = addr / (self.config.row_size * self.config.banks);
+
+        let start = if self.bank_free[bank] > now { self.bank_free[bank] } else { now };
+        self.stats.bank_stall_cycles += start - now;
+
+        let array_latency = if self.open_rows[bank] == Some(row) {
+            self.stats.row_hits += 1;
+            self.config.row_hit_latency
+        } else {
+            self.stats.row_misses += 1;
+            self.open_rows[bank] = Some(row);
+            self.config.row_miss_latency
+        };
+        let data_ready = start + array_latency as u64;
+        self.bank_free[bank] = data_ready;
+
+        let bus_start = if self.bus_free > data_ready { self.bus_free } else { data_ready };
+        self.stats.bus_stall_cycles += bus_start - data_ready;
+        let transfer = words.div_ceil(self.config.bandwidth) as u64;
+        self.bus_free = bus_start + transfer;
+
+        (self.bus_free - now) as u32
+    }
+}
+
+#[derive(Debug, Default, Copy, Clone)]
+pub struct MshrStats {
+    pub primary_misses: u64,
+    pub secondary_misses: u64,
+    pub full_stall_cycles: u64,
+    pub busy_cycles: u64,
+    pub occupancy_sum: u64,
+}
+
+impl MshrStats {
+    // Average number of outstanding misses while at least one is outstanding, 0 when nothing missed
+    pub fn memory_level_parallelism(&self) -> f32 {
+        if self.busy_cycles == 0 { 0.0 } else { self.occupancy_sum as f32 / self.busy_cycles as f32 }
+    }
+
+    pub fn record(&self, stats: &mut Stats) {
+        stats.counter("primary_misses", self.primary_misses);
+        stats.counter("secondary_misses", self.secondary_misses);
+        stats.counter("full_stall_cycles", self.full_stall_cycles);
+        stats.counter("busy_cycles", self.busy_cycles);
+        stats.counter("occupancy_sum", self.occupancy_sum);
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Mshr {
+    line: usize,
+    ready: u64,
+}
+
+// Miss status holding registers tracking the outstanding line fills of a cache
+#[derive(Debug)]
+pub struct MshrFile {
+    entries: Vec<Mshr>,
+    size: usize,
+    pub stats: MshrStats,
+}
+
+impl MshrFile {
+    fn new(size: usize) -> MshrFile {
+        if size == 0 {
+            panic!("At least one MSHR is needed");
+        }
+        MshrFile {
+            entries: Vec::new(),
+            size,
+            stats: MshrStats::default(),
+        }
+    }
+
+    fn outstanding(&self, line: usize) -> Option<u64> {
+        self.entries.iter().find(|m| m.line == line).map(|m| m.ready)
+    }
+
+    fn full(&self) -> bool {
+        self.entries.len() >= self.size
+    }
+
+    fn allocate(&mut self, line: usize, ready: u64, demand: bool) {
+        if demand {
+            self.stats.primary_misses += 1;
+        }
+        self.entries.push(Mshr { line, ready });
+    }
+
+    fn tick(&mut self, now: u64) {
+        self.entries.retain(|m| m.ready > now);
+        if !self.entries.is_empty() {
+            self.stats.busy_cycles += 1;
+            self.stats.occupancy_sum += self.entries.len() as u64;
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Level {
+    pub name: String,
+    pub cache: Cache,
+    // The level misses are sent to, None being main memory
+    next: Option<usize>,
+}
+
+// The private first level caches of one core and the miss handling in front of its L1 data cache
+#[derive(Debug)]
+pub struct CoreCaches {
+    l1i: usize,
+    l1d: usize,
+    pub l1d_mshrs: MshrFile,
+    pub prefetcher: Prefetcher,
+    // Lines brought into the L1 data cache by the prefetcher and not yet used
+    prefetched_lines: Vec<usize>,
+}
+
+// The bus transaction a data access made and how the other caches answered it
+struct Snoop {
+    op: Option<BusOp>,
+    from: LineState,
+    latency: u32,
+    // Some when another cache sends the line, holding whether it arrives dirty
+    supplied: Option<bool>,
+    // Whether any other cache held the line
+    shared: bool,
+}
+
+#[derive(Debug)]
+pub struct MemorySystem {
+    pub levels: Vec<Level>,
+    pub dram: Dram,
+    pub cores: Vec<CoreCaches>,
+    pub coherence: Coherence,
+    // The address each core and hardware thread last load linked, cleared by any store to it
+    reservations: Vec<((usize, usize), usize)>,
+    inclusion: Inclusion,
+    cycle: u64,
+}
+
+impl MemorySystem {
+    // Every core gets its own L1 caches, the L2, L3 and DRAM behind them are shared
+    // and the L1 data caches are kept coherent by snooping a shared bus
+    pub fn new(cores: usize, config: MemoryConfig) -> MemorySystem {
+        if cores == 0 {
+            panic!("At least one core is needed");
+        }
+        let mut levels = Vec::new();
+        let mut core_caches = Vec::new();
+        let shared = if config.l2.is_some() { Some(2 * cores) } else { None };
+        for core in 0..cores {
+            let prefix = if cores == 1 { String::new() } else { format!("Core {} ", core) };
+            levels.push(Level { name: format!("{}L1I", prefix), cache: Cache::new(config.l1i), next: shared });
+            levels.push(Level { name: format!("{}L1D", prefix), cache: Cache::new(config.l1d), next: shared });
+            core_caches.push(CoreCaches {
+                l1i: 2 * core,
+                l1d: 2 * core + 1,
+                l1d_mshrs: MshrFile::new(config.mshrs),
+                prefetcher: config.prefetcher.clone(),
+                prefetched_lines: Vec::new(),
+            });
+        }
+        if let Some(l2) = config.l2 {
+            let next = if config.l3.is_some() { Some(2 * cores + 1) } else { None };
+            levels.push(Level { name: String::from("L2"), cache: Cache::new(l2), next });
+            if let Some(l3) = config.l3 {
+                levels.push(Level { name: String::from("L3"), cache: Cache::new(l3), next: None });
+            }
+        }
+        MemorySystem {
+            levels,
+            dram: Dram::new(config.dram),
+            cores: core_caches,
+            coherence: Coherence::new(config.coherence, cores),
+            reservations: Vec::new(),
+            inclusion: config.inclusion,
+            cycle: 0,
+        }
+    }
+
+    pub fn tick(&mut self) {
+        for core in &mut self.cores {
+            core.l1d_mshrs.tick(self.cycle);
+        }
+        self.cycle += 1;
+    }
+
+    pub fn l1i_line_size(&self) -> usize {
+        self.levels[self.cores[0].l1i].cache.config.line_size
+    }
+
+    pub fn l1d_line_size(&self) -> usize {
+        self.levels[self.cores[0].l1d].cache.config.line_size
+    }
+
+    // Each core's private caches go under that core, whatever the number of cores
+    pub fn record(&self, stats: &mut Stats) {
+        for (c, core) in self.cores.iter().enumerate() {
+            stats.enter(&format!("core{}", c));
+            for &(name, level) in &[("l1i", core.l1i), ("l1d", core.l1d)] {
+                stats.enter(name);
+                self.levels[level].cache.stats.record(stats);
+                stats.leave();
+            }
+            stats.enter("l1d_mshrs");
+            core.l1d_mshrs.stats.record(stats);
+            stats.leave();
+            if core.prefetcher.kind != PrefetcherKind::None {
+                stats.enter("prefetcher");
+                core.prefetcher.stats.record(stats);
+                stats.leave();
+            }
+            stats.leave();
+        }
+        for level in &self.levels[2 * self.cores.len()..] {
+            stats.enter(&stats::key(&level.name));
+            level.cache.stats.record(stats);
+            stats.leave();
+        }
+        if self.coherence.enabled() {
+            stats.enter("coherence");
+            self.coherence.stats.record(stats);
+            stats.histogram("invalidations_received", &self.coherence.invalidations_received);
+            stats.leave();
+        }
+        stats.enter("dram");
+        self.dram.stats.record(stats);
+        stats.leave();
+    }
+
+    pub fn l1d_misses(&self, core: usize) -> u64 {
+        self.levels[self.cores[core].l1d].cache.stats.misses
+    }
+
+    // Hits are pipelined into the fetch stage so only the extra cycles of a miss stall fetch
+    pub fn access_instruction(&mut self, core: usize, pc: usize) -> u32 {
+        let l1i = self.cores[core].l1i;
+        let latency = self.access(l1i, INSTRUCTION_BASE + pc, false);
+        latency - self.levels[l1i].cache.config.hit_latency
+    }
+
+    // Returns None when the access misses and every MSHR is busy so it has to be retried
+    pub fn access_data(&mut self, core: usize, addr: usize, write: bool, pc: usize) -> Option<u32> {
+        let l1d = self.cores[core].l1d;
+        let config = self.levels[l1d].cache.config;
+        let line = addr / config.line_size;
+        let outstanding = self.cores[core].l1d_mshrs.outstanding(line);
+        let present = self.levels[l1d].cache.probe(addr);
+        let allocates = !write || config.write_allocate;
+        let miss = allocates && !present && outstanding.is_none();
+
+        if miss && self.cores[core].l1d_mshrs.full() {
+            self.cores[core].l1d_mshrs.stats.full_stall_cycles += 1;
+            return None;
+        }
+        if write {
+            self.reservations.retain(|&(_, linked)| linked != addr);
+        }
+        let snoop = self.snoop(core, addr, write, allocates);
+        let bus = snoop.latency;
+
+        let prefetch_hit = match self.cores[core].prefetched_lines.iter().position(|&l| l == line) {
+            Some(position) => {
+                self.cores[core].prefetched_lines.swap_remove(position);
+                if present || outstanding.is_some() {
+                    self.cores[core].prefetcher.stats.useful += 1;
+                    if outstanding.is_some() {
+                        self.cores[core].prefetcher.stats.late += 1;
+                    }
+                    true
+                } else { false }
+            },
+            None => false,
+        };
+
+        let latency = if let Some(ready) = outstanding {
+            self.cores[core].l1d_mshrs.stats.secondary_misses += 1;
+            self.levels[l1d].cache.merge(addr, write);
+            let remaining = (ready - self.cycle) as u32;
+            if remaining > config.hit_latency { remaining } else { config.hit_latency }
+        } else if miss && snoop.supplied.is_some() {
+            // Another core's cache sends the line instead of the next level
+            let dirty = snoop.supplied.unwrap_or(false);
+            self.levels[l1d].cache.lookup(addr, write);
+            let victim = self.levels[l1d].cache.insert(addr, dirty || (write && config.write_back));
+            self.evict(l1d, victim);
+            let mut latency = config.hit_latency + self.coherence.config.transfer_latency;
+            self.cores[core].l1d_mshrs.allocate(line, self.cycle + (bus + latency) as u64, true);
+            if write && !config.write_back {
+                let next = self.levels[l1d].next;
+                latency += self.write_through(next, addr, config.line_size);
+            }
+            latency
+        } else if miss && self.cores[core].prefetcher.kind == PrefetcherKind::Stream {
+            match self.cores[core].prefetcher.stream_take(line) {
+                Some(ready) => {
+                    self.cores[core].prefetcher.stats.useful += 1;
+                    if ready > self.cycle {
+                        self.cores[core].prefetcher.stats.late += 1;
+                    }
+                    // The line moves from the stream buffer into the cache
+                    self.levels[l1d].cache.lookup(addr, write);
+                    let victim = self.levels[l1d].cache.insert(addr, write && config.write_back);
+                    self.evict(l1d, victim);
+                    let lines = self.cores[core].prefetcher.stream_refill(line);
+                    self.stream_fetch(core, lines);
+                    self.settle(core, addr, &snoop);
+                    let remaining = if ready > self.cycle { (ready - self.cycle) as u32 } else { 0 };
+                    return Some(bus + if remaining > config.hit_latency { remaining } else { config.hit_latency });
+                },
+                None => {
+                    let latency = self.access(l1d, addr, write);
+                    self.cores[core].l1d_mshrs.allocate(line, self.cycle + (bus + latency) as u64, true);
+                    self.settle(core, addr, &snoop);
+                    let lines = self.cores[core].prefetcher.stream_restart(line);
+                    self.stream_fetch(core, lines);
+                    return Some(bus + latency);
+                },
+            }
+        } else {
+            let latency = self.access(l1d, addr, write);
+            if miss {
+                self.cores[core].l1d_mshrs.allocate(line, self.cycle + (bus + latency) as u64, true);
+            }
+            latency
+        };
+        self.settle(core, addr, &snoop);
+
+        let lines = self.cores[core].prefetcher.observe(pc, addr, config.line_size, miss, prefetch_hit);
+        for prefetch_line in lines {
+            self.prefetch(core, prefetch_line);
+        }
+        Some(bus + latency)
+    }
+
+    pub fn link(&mut self, core: usize, thread: usize, addr: usize) {
+        self.unlink(core, thread);
+        self.reservations.push(((core, thread), addr));
+    }
+
+    pub fn linked(&self, core: usize, thread: usize, addr: usize) -> bool {
+        self.reservations.contains(&((core, thread), addr))
+    }
+
+    pub fn unlink(&mut self, core: usize, thread: usize) {
+        self.reservations.retain(|&(context, _)| context != (core, thread));
+    }
+
+    // Puts the transaction a data access needs on the bus, moving every other core's copy of the line on
+    fn snoop(&mut self, core: usize, addr: usize, write: bool, allocates: bool) -> Snoop {
+        let l1d = self.cores[core].l1d;
+        let line_size = self.levels[l1d].cache.config.line_size;
+        let line = addr / line_size;
+        let present = self.levels[l1d].cache.probe(addr);
+        let state = self.coherence.state(core, line, present);
+        let mut snoop = Snoop { op: None, from: state, latency: 0, supplied: None, shared: false };
+        if !self.coherence.enabled() {
+            return snoop;
+        }
+        snoop.op = self.coherence.request(state, write, allocates);
+        let op = match snoop.op {
+            Some(op) => op,
+            None => {
+                if write && state == LineState::E {
+                    self.coherence.set(core, line, state, LineState::M, "write hit", self.cycle);
+                }
+                return snoop;
+            },
+        };
+        snoop.latency = self.coherence.config.snoop_latency;
+        for peer in 0..self.cores.len() {
+            if peer == core {
+                continue;
+            }
+            let peer_l1d = self.cores[peer].l1d;
+            let peer_state = self.coherence.state(peer, line, self.levels[peer_l1d].cache.probe(addr));
+            if peer_state == LineState::I {
+                continue;
+            }
+            snoop.shared = true;
+            let reaction = self.coherence.snoop(peer_state, op);
+            let dirty = if reaction.next == LineState::I {
+                self.cores[peer].prefetched_lines.retain(|&l| l != line);
+                self.levels[peer_l1d].cache.invalidate(addr).unwrap_or(false)
+            } else if reaction.flushes {
+                self.levels[peer_l1d].cache.clean(addr)
+            } else {
+                false
+            };
+            if reaction.flushes && dirty {
+                self.coherence.stats.flushes += 1;
+                let next = self.levels[peer_l1d].next;
+                self.write_through(next, addr, line_size);
+            }
+            if reaction.supplies {
+                self.coherence.stats.cache_to_cache += 1;
+                snoop.supplied = Some(dirty && !reaction.flushes);
+            }
+            let cause = format!("core {} {:?}", core, op);
+            self.coherence.set(peer, line, peer_state, reaction.next, &cause, self.cycle);
+        }
+        snoop
+    }
+
+    // Records the state the requesting cache holds the line in once its transaction is done
+    fn settle(&mut self, core: usize, addr: usize, snoop: &Snoop) {
+        let op = match snoop.op {
+            Some(op) => op,
+            None => return,
+        };
+        let l1d = self.cores[core].l1d;
+        if op == BusOp::BusWr || !self.levels[l1d].cache.probe(addr) {
+            return;
+        }
+        let next = match op {
+            BusOp::BusRd if snoop.shared => LineState::S,
+            BusOp::BusRd => LineState::E,
+            _ => LineState::M,
+        };
+        let line = addr / self.levels[l1d].cache.config.line_size;
+        self.coherence.set(core, line, snoop.from, next, &format!("{:?}", op), self.cycle);
+    }
+
+    fn prefetch(&mut self, core: usize, line: usize) {
+        let l1d = self.cores[core].l1d;
+        let config = self.levels[l1d].cache.config;
+        let addr = line * config.line_size;
+        if self.levels[l1d].cache.probe(addr) || self.cores[core].l1d_mshrs.outstanding(line).is_some() {
+            return;
+        }
+        if self.cores[core].l1d_mshrs.full() {
+            self.cores[core].prefetcher.stats.dropped += 1;
+            return;
+        }
+        self.cores[core].prefetcher.stats.issued += 1;
+        let snoop = self.snoop(core, addr, false, true);
+        let next = self.levels[l1d].next;
+        let (fill_latency, dirty) = match snoop.supplied {
+            Some(dirty) => (self.coherence.config.transfer_latency, dirty),
+            None => self.fetch(next, addr, config.line_size),
+        };
+        let victim = self.levels[l1d].cache.insert(addr, dirty);
+        self.evict(l1d, victim);
+        self.settle(core, addr, &snoop);
+        let ready = self.cycle + (snoop.latency + config.hit_latency + fill_latency) as u64;
+        self.cores[core].l1d_mshrs.allocate(line, ready, false);
+        self.cores[core].prefetched_lines.push(line);
+    }
+
+    fn stream_fetch(&mut self, core: usize, lines: Vec<usize>) {
+        let l1d = self.cores[core].l1d;
+        let config = self.levels[l1d].cache.config;
+        let next = self.levels[l1d].next;
+        for line in lines {
+            self.cores[core].prefetcher.stats.issued += 1;
+            let (fill_latency, _) = self.fetch(next, line * config.line_size, config.line_size);
+            let ready = self.cycle + fill_latency as u64;
+            self.cores[core].prefetcher.stream_push(line, ready);
+        }
+    }
+
+    // An access from the core to one of the first level caches
+    fn access(&mut self, level: usize, addr: usize, write: bool) -> u32 {
+        let config = self.levels[level].cache.config;
+        let next = self.levels[level].next;
+        let mut latency = config.hit_latency;
+
+        let hit = self.levels[level].cache.lookup(addr, write);
+        if hit || (write && !config.write_allocate) {
+            if write && (!config.write_back || !hit) {
+                latency += self.write_through(next, addr, config.line_size);
+            }
+            return latency;
+        }
+
+        let (fill_latency, dirty) = self.fetch(next, addr, config.line_size);
+        latency += fill_latency;
+        let victim = self.levels[level].cache.insert(addr, dirty || (write && config.write_back));
+        self.evict(level, victim);
+        if write && !config.write_back {
+            latency += self.write_through(next, addr, config.line_size);
+        }
+        latency
+    }
+
+    // Fetches a line for the level above, returning the latency and whether the line arrives dirty
+    fn fetch(&mut self, level: Option<usize>, addr: usize, words: usize) -> (u32, bool) {
+        let level = match level {
+            Some(level) => level,
+            None => return (self.dram.access(self.cycle, addr, words, false), false),
+        };
+        let config = self.levels[level].cache.config;
+        let next = self.levels[level].next;
+        let latency = config.hit_latency;
+
+        let hit = self.levels[level].cache.lookup(addr, false);
+        if self.inclusion == Inclusion::Exclusive {
+            // The line moves up so it must leave this level
+            if hit {
+                let dirty = self.levels[level].cache.invalidate(addr).unwrap_or(false);
+                return (latency, dirty);
+            }
+            let (fill_latency, dirty) = self.fetch(next, addr, words);
+            return (latency + fill_latency, dirty);
+        }
+
+        if hit {
+            return (latency, false);
+        }
+        let (fill_latency, dirty) = self.fetch(next, addr, config.line_size);
+        let victim = self.levels[level].cache.insert(addr, dirty);
+        self.evict(level, victim);
+        (latency + fill_latency, false)
+    }
+
+    fn write_through(&mut self, level: Option<usize>, addr: usize, words: usize) -> u32 {
+        match level {
+            Some(level) => {
+                let latency = self.levels[level].cache.config.hit_latency;
+                let victim = self.levels[level].cache.insert(addr, true);
+                self.evict(level, victim);
+                latency
+            },
+            None => self.dram.access(self.cycle, addr, words, true),
+        }
+    }
+
+    fn evict(&mut self, level: usize, victim: Option<Victim>) {
+        let victim = match victim {
+            Some(v) => v,
+            None => return,
+        };
+        let line_size = self.levels[level].cache.config.line_size;
+        if let Some(core) = self.cores.iter_mut().find(|c| c.l1d == level) {
+            let line = victim.addr / line_size;
+            core.prefetched_lines.retain(|&l| l != line);
+        }
+        let mut dirty = victim.dirty;
+        if self.inclusion == Inclusion::Inclusive {
+            dirty |= self.back_invalidate(level, victim.addr, line_size);
+        }
+        if !dirty && self.inclusion != Inclusion::Exclusive {
+            return;
+        }
+        match self.levels[level].next {
+            Some(next) => {
+                let next_victim = self.levels[next].cache.insert(victim.addr, dirty);
+                self.evict(next, next_victim);
+            },
+            None => {
+                if dirty {
+                    let now = self.cycle;
+                    self.dram.access(now, victim.addr, line_size, true);
+                }
+            },
+        }
+    }
+
+    // Removes every copy of a line from the levels above, returning whether any were dirty
+    fn back_invalidate(&mut self, level: usize, addr: usize, words: usize) -> bool {
+        let mut dirty = false;
+        for upper in 0..self.levels.len() {
+            if self.levels[upper].next != Some(level) {
+                continue;
+            }
+            let step = self.levels[upper].cache.config.line_size;
+            let mut a = addr;
+            while a < addr + words {
+                if let Some(d) = self.levels[upper].cache.invalidate(a) {
+                    dirty |= d;
+                }
+                a += step;
+            }
+            dirty |= self.back_invalidate(upper, addr, words);
+        }
+        dirty
+    }
+}
@@ -0,0 +1,428 @@
+use std::fmt;
+use hierarchy::MemorySystem;
+use stats::Stats;
+use super::{compute, BranchPredictor, Core, DecodedInstruction, EncodedInstruction, ExecResult, FUConfig, FUType, InstructionKind, Op, Source, MEM_SIZE};
+
+// The classic IF ID EX MEM WB pipeline. Each stage holds a group of up to width instructions
+// that moves on as a whole, so a width of one is the textbook scalar pipeline.
+
+#[derive(Debug, Copy, Clone)]
+struct Slot {
+    pc: usize,
+    instruction: EncodedInstruction,
+    decoded: DecodedInstruction,
+    // Where fetch carried on after this instruction
+    predicted: usize,
+    // The result, the loaded value or the value to store
+    value: u32,
+    addr: usize,
+}
+
+#[derive(Debug, Default)]
+pub struct InOrderStats {
+    pub retired: u64,
+    // Cycles decode issued nothing because a source was not ready or forwardable yet
+    pub raw_stall_cycles: u64,
+    pub load_use_stall_cycles: u64,
+    // Cycles decode issued nothing because execute was still busy
+    pub ex_busy_stall_cycles: u64,
+    pub mem_stall_cycles: u64,
+    pub forwarded_operands: u64,
+    pub flushes: u64,
+    pub flushed_instructions: u64,
+    // Fetch bubbles from jumps and predicted taken branches being spotted in decode
+    pub decode_redirects: u64,
+    pub icache_stall_cycles: u64,
+    // Cycles issuing each number of instructions from zero up to the width
+    pub issue_widths: Vec<u64>,
+}
+
+impl InOrderStats {
+    fn record(&self, stats: &mut Stats) {
+        stats.counter("instructions", self.retired);
+        stats.counter("raw_stall_cycles", self.raw_stall_cycles);
+        stats.counter("load_use_stall_cycles", self.load_use_stall_cycles);
+        stats.counter("ex_busy_stall_cycles", self.ex_busy_stall_cycles);
+        stats.counter("mem_stall_cycles", self.mem_stall_cycles);
+        stats.counter("forwarded_operands", self.forwarded_operands);
+        stats.counter("flushes", self.flushes);
+        stats.counter("flushed_instructions", self.flushed_instructions);
+        stats.counter("decode_redirects", self.decode_redirects);
+        stats.counter("icache_stall_cycles", self.icache_stall_cycles);
+        stats.histogram("issue_widths", &self.issue_widths);
+    }
+}
+
+pub struct InOrderCore {
+    width: usize,
+    forwarding: bool,
+    instructions: Vec<EncodedInstruction>,
+    pc: usize,
+    // This cycle's fetch is squashed by a redirect
+    redirect: bool,
+    fetch_stall: u32,
+    filled_line: Option<usize>,
+    latencies: Vec<(Op, FUType, u32)>,
+    units: Vec<(FUType, usize)>,
+    id: Vec<Slot>,
+    ex: Vec<Slot>,
+    ex_remaining: u32,
+    ex_resolved: bool,
+    mem: Vec<Slot>,
+    mem_remaining: u32,
+    mem_pending: bool,
+    wb: Vec<Slot>,
+    registers: [u32; 32],
+    pub branch_predictor: BranchPredictor,
+    pub stats: InOrderStats,
+}
+
+impl fmt::Debug for InOrderCore {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let stage = |slots: &Vec<Slot>| slots.iter().map(|s| format!("{}: {:?}", s.pc, s.instruction)).collect::<Vec<String>>();
+        write!(f, "IF: {}\nID: {:?}\nEX: {:?}\nMEM: {:?}\nWB: {:?}", self.pc, stage(&self.id), stage(&self.ex), stage(&self.mem), stage(&self.wb))
+    }
+}
+
+impl InOrderCore {
+    pub fn new(instructions: Vec<EncodedInstruction>, pred_type: usize, width: usize, forwarding: bool, fu_pool: &[FUConfig]) -> InOrderCore {
+        if width == 0 {
+            panic!("The in order core needs an issue width of at least one");
+        }
+        let mut latencies = Vec::new();
+        let mut units: Vec<(FUType, usize)> = Vec::new();
+        for config in fu_pool {
+            for &(op, latency) in &config.latencies {
+                latencies.push((op, config.fu_type, latency));
+            }
+            match units.iter_mut().find(|&&mut (fu_type, _)| fu_type == config.fu_type) {
+                Some(unit) => unit.1 += config.count,
+                None => units.push((config.fu_type, config.count)),
+            }
+        }
+        InOrderCore {
+            width,
+            forwarding,
+            instructions,
+            pc: 0,
+            redirect: false,
+            fetch_stall: 0,
+            filled_line: None,
+            latencies,
+            units,
+            id: Vec::new(),
+            ex: Vec::new(),
+            ex_remaining: 0,
+            ex_resolved: false,
+            mem: Vec::new(),
+            mem_remaining: 0,
+            mem_pending: false,
+            wb: Vec::new(),
+            registers: [0; 32],
+            branch_predictor: BranchPredictor::new(pred_type),
+            stats: InOrderStats {
+                issue_widths: vec![0; width + 1],
+                ..Default::default()
+            },
+        }
+    }
+
+    fn writeback(&mut self) {
+        for slot in self.wb.drain(..) {
+            if let Some(d) = slot.decoded.dest {
+                self.registers[d] = slot.value;
+            }
+            self.stats.retired += 1;
+        }
+    }
+
+    fn memory(&mut self, memory: &mut [u32; MEM_SIZE], mem_system: &mut MemorySystem) {
+        if self.mem.is_empty() {
+            return;
+        }
+        if self.mem_pending {
+            let slot = self.mem.iter_mut().find(|s| s.decoded.is_memory()).unwrap();
+            let write = slot.decoded.kind == InstructionKind::Store;
+            match mem_system.access_data(0, slot.addr, write, slot.pc) {
+                Some(latency) => {
+                    if write {
+                        memory[slot.addr] = slot.value;
+                    } else {
+                        slot.value = memory[slot.addr];
+                    }
+                    self.mem_remaining = if latency == 0 { 1 } else { latency };
+                    self.mem_pending = false;
+                },
+                None => {
+                    self.stats.mem_stall_cycles += 1;
+                    return;
+                },
+            }
+        }
+        if self.mem_remaining > 0 {
+            self.mem_remaining -= 1;
+        }
+        if self.mem_remaining == 0 {
+            self.wb = self.mem.drain(..).collect();
+        } else {
+            self.stats.mem_stall_cycles += 1;
+        }
+    }
+
+    fn execute(&mut self) {
+        if self.ex.is_empty() {
+            return;
+        }
+        if !self.ex_resolved {
+            self.ex_resolved = true;
+            if let Some(branch) = self.ex.iter().find(|s| s.decoded.kind == InstructionKind::Branch).cloned() {
+                let taken_pc = if branch.value != 0 { branch.decoded.target } else { branch.pc + 1 };
+                if !self.branch_predictor.prediction_correct(branch.predicted, taken_pc, branch.pc) {
+                    self.stats.flushes += 1;
+                    self.stats.flushed_instructions += self.id.len() as u64;
+                    self.id.clear();
+                    self.pc = taken_pc;
+                    self.redirect = true;
+                    self.fetch_stall = 0;
+                    self.filled_line = None;
+                }
+            }
+        }
+        if self.ex_remaining > 0 {
+            self.ex_remaining -= 1;
+        }
+        if self.ex_remaining == 0 && self.mem.is_empty() {
+            self.mem_pending = self.ex.iter().any(|s| s.decoded.is_memory());
+            self.mem = self.ex.drain(..).collect();
+        }
+    }
+
+    // The newest value of a register from an older instruction still in flight, or the register file
+    fn read(&mut self, reg: usize) -> u32 {
+        for slot in self.mem.iter().rev().chain(self.wb.iter().rev()) {
+            if slot.decoded.dest == Some(reg) {
+                self.stats.forwarded_operands += 1;
+                return slot.value;
+            }
+        }
+        self.registers[reg]
+    }
+
+    fn operand(&mut self, source: Source) -> u32 {
+        match source {
+            Source::Reg(r) => self.read(r),
+            Source::Imm(imm) => imm,
+        }
+    }
+
+    fn fu(&self, op: Op) -> (FUType, u32) {
+        match self.latencies.iter().find(|&&(o, _, _)| o == op) {
+            Some(&(_, fu_type, latency)) => (fu_type, latency),
+            None => panic!("No functional unit can execute {:?}", op),
+        }
+    }
+
+    // Whether a source is produced by an older instruction that cannot hand it over yet.
+    // Results in writeback are written in the first half of the cycle so can always be read.
+    fn hazard(&self, slot: &Slot) -> Option<bool> {
+        for producer in self.mem.iter().rev() {
+            if let Some(d) = producer.decoded.dest {
+                if slot.decoded.reads(d) {
+                    if producer.decoded.kind == InstructionKind::Load {
+                        return Some(true);
+                    }
+                    return if self.forwarding { None } else { Some(false) };
+                }
+            }
+        }
+        for producer in self.wb.iter().rev() {
+            if let Some(d) = producer.decoded.dest {
+                if slot.decoded.reads(d) {
+                    return if self.forwarding { None } else { Some(false) };
+                }
+            }
+        }
+        None
+    }
+
+    fn decode(&mut self) {
+        if self.id.is_empty() {
+            self.stats.issue_widths[0] += 1;
+            return;
+        }
+        if !self.ex.is_empty() {
+            self.stats.ex_busy_stall_cycles += 1;
+            self.stats.issue_widths[0] += 1;
+            return;
+        }
+
+        //Find the longest prefix of the group that can issue together
+        let mut used: Vec<(FUType, usize)> = Vec::new();
+        let mut memory_ops = 0;
+        let mut count = 0;
+        let mut load_use = false;
+        for i in 0..self.id.len() {
+            let slot = self.id[i];
+            if self.id[..i].iter().any(|older| older.decoded.dest.is_some_and(|d| slot.decoded.reads(d))) {
+                break;
+            }
+            if let Some(is_load) = self.hazard(&slot) {
+                if i == 0 {
+                    load_use = is_load;
+                }
+                break;
+            }
+            match slot.decoded.kind {
+                InstructionKind::Load | InstructionKind::Store => {
+                    if memory_ops == 1 {
+                        break;
+                    }
+                    memory_ops += 1;
+                },
+                InstructionKind::Alu | InstructionKind::Branch => {
+                    let (fu_type, _) = self.fu(slot.decoded.op);
+                    let available = self.units.iter().find(|&&(t, _)| t == fu_type).map_or(0, |&(_, n)| n);
+                    let in_use = used.iter().find(|&&(t, _)| t == fu_type).map_or(0, |&(_, n)| n);
+                    if in_use == available {
+                        break;
+                    }
+                    match used.iter_mut().find(|&&mut (t, _)| t == fu_type) {
+                        Some(unit) => unit.1 += 1,
+                        None => used.push((fu_type, 1)),
+                    }
+                },
+            }
+            count += 1;
+            if slot.decoded.kind == InstructionKind::Branch {
+                break;
+            }
+        }
+
+        self.stats.issue_widths[count] += 1;
+        if count == 0 {
+            if load_use {
+                self.stats.load_use_stall_cycles += 1;
+            } else {
+                self.stats.raw_stall_cycles += 1;
+            }
+            return;
+        }
+
+        let mut latency = 1;
+        let group: Vec<Slot> = self.id.drain(..count).collect();
+        for mut slot in group {
+            let op1 = self.operand(slot.decoded.sources[0]);
+            let op2 = self.operand(slot.decoded.sources[1]);
+            match slot.decoded.kind {
+                InstructionKind::Load | InstructionKind::Store => {
+                    slot.addr = op1 as usize;
+                    slot.value = op2;
+                },
+                InstructionKind::Alu | InstructionKind::Branch => {
+                    let (_, op_latency) = self.fu(slot.decoded.op);
+                    if op_latency > latency {
+                        latency = op_latency;
+                    }
+                    slot.value = match compute(slot.decoded.op, op1, op2, slot.decoded.target) {
+                        ExecResult::Value(value) => value,
+                        ExecResult::BranchTaken(_) => 1,
+                        ExecResult::BranchNotTaken() => 0,
+                        ExecResult::Store => panic!("Computed a store"),
+                    };
+                },
+            }
+            self.ex.push(slot);
+        }
+        self.ex_remaining = latency;
+        self.ex_resolved = false;
+    }
+
+    fn fetch(&mut self, mem_system: &mut MemorySystem) {
+        if self.redirect {
+            self.redirect = false;
+            return;
+        }
+        if self.fetch_stall > 0 {
+            self.fetch_stall -= 1;
+            self.stats.icache_stall_cycles += 1;
+            return;
+        }
+        if self.pc >= self.instructions.len() || self.id.len() == self.width {
+            return;
+        }
+
+        //Fetch blocks are line aligned so one instruction cache access covers the block
+        let line_size = mem_system.l1i_line_size();
+        let line = self.pc / line_size;
+        if self.filled_line != Some(line) {
+            let stall = mem_system.access_instruction(0, self.pc);
+            if stall > 0 {
+                self.fetch_stall = stall - 1;
+                self.stats.icache_stall_cycles += 1;
+                self.filled_line = Some(line);
+                return;
+            }
+        }
+        self.filled_line = None;
+
+        while self.id.len() < self.width && self.pc < self.instructions.len() {
+            let pc = self.pc;
+            let instruction = self.instructions[pc];
+            let next = match instruction {
+                EncodedInstruction::J(inst) => inst,
+                EncodedInstruction::Beq(_, _, _) |
+                EncodedInstruction::Beqz(_, _) |
+                EncodedInstruction::Blt(_, _, _) |
+                EncodedInstruction::Bgt(_, _, _) => self.branch_predictor.predict(instruction, pc),
+                _ => pc + 1,
+            };
+            if let Some(decoded) = DecodedInstruction::new(instruction) {
+                self.id.push(Slot {
+                    pc,
+                    instruction,
+                    decoded,
+                    predicted: next,
+                    value: 0,
+                    addr: 0,
+                });
+            }
+            self.pc = next;
+            //Jumps and taken predictions are only spotted in decode so the next fetch is lost
+            if next != pc + 1 {
+                self.stats.decode_redirects += 1;
+                self.redirect = true;
+                break;
+            }
+            if self.pc.is_multiple_of(line_size) {
+                break;
+            }
+        }
+    }
+}
+
+impl Core for InOrderCore {
+    // Stages run back to front so each group moves into the space the one ahead just left
+    fn cycle(&mut self, memory: &mut [u32; MEM_SIZE], mem_system: &mut MemorySystem) {
+        self.writeback();
+        self.memory(memory, mem_system);
+        self.execute();
+        self.decode();
+        self.fetch(mem_system);
+    }
+
+    fn finished(&self) -> bool {
+        self.pc >= self.instructions.len() &&
+        self.id.is_empty() && self.ex.is_empty() && self.mem.is_empty() && self.wb.is_empty()
+    }
+
+    fn registers(&self) -> &[u32; 32] {
+        &self.registers
+    }
+
+    fn record(&self, stats: &mut Stats) {
+        self.stats.record(stats);
+        stats.enter("branch_predictor");
+        self.branch_predictor.record(stats);
+        stats.leave();
+    }
+}
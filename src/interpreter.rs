@@ -0,0 +1,123 @@
+use super::{compute, DecodedInstruction, EncodedInstruction, ExecResult, InstructionKind, Source, MEM_SIZE};
+
+// The reference semantics of the instruction set. Hardware contexts take turns running one whole
+// instruction with no timing, so memory is sequentially consistent and fences have nothing to order.
+
+#[derive(Debug)]
+struct Context {
+    instructions: Vec<EncodedInstruction>,
+    pc: usize,
+    registers: [u32; 32],
+    memory: usize,
+    // The address of the last load linked, cleared by any store to it
+    link: Option<usize>,
+    executed: u64,
+}
+
+#[derive(Debug)]
+pub struct Interpreter {
+    contexts: Vec<Context>,
+    pub memories: Vec<[u32; MEM_SIZE]>,
+}
+
+impl Interpreter {
+    pub fn new(memories: Vec<[u32; MEM_SIZE]>) -> Interpreter {
+        Interpreter {
+            contexts: Vec::new(),
+            memories,
+        }
+    }
+
+    // Contexts given the same memory share it, as cores do, and see each other's stores
+    pub fn add_context(&mut self, instructions: Vec<EncodedInstruction>, memory: usize, registers: [u32; 32]) {
+        self.contexts.push(Context {
+            instructions,
+            pc: 0,
+            registers,
+            memory,
+            link: None,
+            executed: 0,
+        });
+    }
+
+    pub fn registers(&self, context: usize) -> &[u32; 32] {
+        &self.contexts[context].registers
+    }
+
+    pub fn executed(&self, context: usize) -> u64 {
+        self.contexts[context].executed
+    }
+
+    fn finished(&self, context: usize) -> bool {
+        self.contexts[context].pc >= self.contexts[context].instructions.len()
+    }
+
+    // Runs the contexts round robin until all of them fall off the end of their programs
+    pub fn run(&mut self) {
+        while (0..self.contexts.len()).any(|c| !self.finished(c)) {
+            for c in 0..self.contexts.len() {
+                if !self.finished(c) {
+                    self.step(c);
+                }
+            }
+        }
+    }
+
+    fn step(&mut self, c: usize) {
+        let instruction = self.contexts[c].instructions[self.contexts[c].pc];
+        let memory = self.contexts[c].memory;
+        let mut next = self.contexts[c].pc + 1;
+        match instruction {
+            EncodedInstruction::Noop | EncodedInstruction::Fence => (),
+            EncodedInstruction::Halt => next = self.contexts[c].instructions.len(),
+            EncodedInstruction::J(target) => next = target,
+            EncodedInstruction::Ll(addr, dest) => {
+                let addr = self.contexts[c].registers[addr] as usize;
+                self.contexts[c].registers[dest] = self.memories[memory][addr];
+                self.contexts[c].link = Some(addr);
+            },
+            //Succeeds and writes 1 only if nothing has stored to the address since the load linked
+            EncodedInstruction::Sc(addr, val, dest) => {
+                let addr = self.contexts[c].registers[addr] as usize;
+                let success = self.contexts[c].link == Some(addr);
+                self.contexts[c].link = None;
+                if success {
+                    let value = self.contexts[c].registers[val];
+                    self.store(memory, addr, value);
+                }
+                self.contexts[c].registers[dest] = success as u32;
+            },
+            _ => {
+                let decoded = DecodedInstruction::new(instruction).unwrap();
+                let registers = self.contexts[c].registers;
+                let operand = |source: Source| match source {
+                    Source::Reg(r) => registers[r],
+                    Source::Imm(imm) => imm,
+                };
+                let (op1, op2) = (operand(decoded.sources[0]), operand(decoded.sources[1]));
+                match decoded.kind {
+                    InstructionKind::Load => {
+                        self.contexts[c].registers[decoded.dest.unwrap()] = self.memories[memory][op1 as usize];
+                    },
+                    InstructionKind::Store => self.store(memory, op1 as usize, op2),
+                    InstructionKind::Alu | InstructionKind::Branch => match compute(decoded.op, op1, op2, decoded.target) {
+                        ExecResult::Value(value) => self.contexts[c].registers[decoded.dest.unwrap()] = value,
+                        ExecResult::BranchTaken(target) => next = target,
+                        _ => (),
+                    },
+                }
+            },
+        }
+        self.contexts[c].pc = next;
+        self.contexts[c].executed += 1;
+    }
+
+    fn store(&mut self, memory: usize, addr: usize, value: u32) {
+        self.memories[memory][addr] = value;
+        for context in &mut self.contexts {
+            if context.memory == memory && context.link == Some(addr) {
+                context.link = None;
+            }
+        }
+    }
+}
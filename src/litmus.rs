@@ -0,0 +1,112 @@
+use std::collections::BTreeMap;
+use rand::Rng;
+use hierarchy::MemorySystem;
+use super::{assemble, step, Consistency, EncodedInstruction, CPU, MEM_SIZE};
+
+// A small multicore program whose outcome, the values some registers end up with, shows the
+// order memory operations on different cores took effect in. Location x is word 40 and y word 44.
+// Every store writes its own address so the programs fit in one instruction cache line, and a
+// load's outcome is 1 if it saw the store and 0 if it saw the initial zero.
+struct Litmus {
+    name: &'static str,
+    description: &'static str,
+    programs: Vec<&'static str>,
+    // The registers making up the outcome with the names they are reported under, as (name, core, register)
+    observed: Vec<(&'static str, usize, usize)>,
+    // The outcome only a relaxed model can produce and whether TSO is relaxed enough to allow it
+    relaxed: Vec<u32>,
+    tso_allows: bool,
+}
+
+fn tests() -> Vec<Litmus> {
+    vec![
+        Litmus {
+            name: "MP",
+            description: "message passing, core 0 stores x then y, core 1 loads y then x",
+            programs: vec![
+                "LDC 1 40\nLDC 2 44\nSW 1 1\nSW 2 2",
+                "LDC 1 40\nLDC 2 44\nLW 2 5\nLW 1 6",
+            ],
+            observed: vec![("r1", 1, 5), ("r2", 1, 6)],
+            relaxed: vec![1, 0],
+            tso_allows: false,
+        },
+        Litmus {
+            name: "SB",
+            description: "store buffering, core 0 stores x then loads y, core 1 stores y then loads x",
+            programs: vec![
+                "LDC 1 40\nLDC 2 44\nSW 1 1\nLW 2 5",
+                "LDC 1 40\nLDC 2 44\nSW 2 2\nLW 1 5",
+            ],
+            observed: vec![("r1", 0, 5), ("r2", 1, 5)],
+            relaxed: vec![0, 0],
+            tso_allows: true,
+        },
+        Litmus {
+            name: "IRIW",
+            description: "independent reads of independent writes, cores 0 and 1 store x and y, core 2 loads x then y, core 3 loads y then x",
+            programs: vec![
+                "LDC 1 40\nSW 1 1",
+                "LDC 2 44\nSW 2 2",
+                "LDC 1 40\nLDC 2 44\nLW 1 5\nLW 2 6",
+                "LDC 1 40\nLDC 2 44\nLW 2 5\nLW 1 6",
+            ],
+            observed: vec![("r1", 2, 5), ("r2", 2, 6), ("r3", 3, 5), ("r4", 3, 6)],
+            relaxed: vec![1, 0, 1, 0],
+            tso_allows: false,
+        },
+    ]
+}
+
+fn outcome_name(test: &Litmus, outcome: &[u32]) -> String {
+    let values: Vec<String> = test.observed.iter().zip(outcome.iter()).map(|(&(name, _, _), value)| format!("{}={}", name, value)).collect();
+    values.join(" ")
+}
+
+// Runs a test on fresh cores and memory, each core starting after a random delay to vary the interleaving
+fn run_once(test: &Litmus, model: Consistency, max_delay: u32, new_cpu: &dyn Fn(Vec<EncodedInstruction>, usize, Consistency) -> CPU, new_mem_system: &dyn Fn(usize) -> MemorySystem) -> Vec<u32> {
+    let mut mem_system = new_mem_system(test.programs.len());
+    let mut memories = vec![[0; MEM_SIZE]];
+    let mut cpus: Vec<CPU> = test.programs.iter().enumerate().map(|(core, program)| {
+        let mut cpu = new_cpu(assemble(program.lines().map(String::from).collect()), core, model);
+        cpu.threads[0].fetch_unit.stall_cycles = rand::thread_rng().gen_range(0, max_delay + 1);
+        cpu
+    }).collect();
+    while !cpus.iter().all(|cpu| cpu.finished()) {
+        for cpu in cpus.iter_mut().filter(|cpu| !cpu.finished()) {
+            step(cpu, &mut memories, &mut mem_system);
+        }
+        mem_system.tick();
+    }
+    test.observed.iter().map(|&(_, core, reg)| (cpus[core].threads[0].registers.gprs[reg] != 0) as u32).collect()
+}
+
+// Runs the named test, or all of them, many times under each model and reports the outcomes seen
+pub fn run(name: &str, runs: usize, max_delay: u32, new_cpu: &dyn Fn(Vec<EncodedInstruction>, usize, Consistency) -> CPU, new_mem_system: &dyn Fn(usize) -> MemorySystem) {
+    let selected: Vec<Litmus> = tests().into_iter().filter(|t| name == "all" || t.name.to_lowercase() == name.to_lowercase()).collect();
+    if selected.is_empty() {
+        panic!("Unknown litmus test {}", name);
+    }
+    for test in selected {
+        println!("Litmus test {}: {}", test.name, test.description);
+        let mut relaxed_under = Vec::new();
+        for &(model, model_name) in &[(Consistency::Sequential, "SC"), (Consistency::Tso, "TSO")] {
+            let mut outcomes: BTreeMap<Vec<u32>, usize> = BTreeMap::new();
+            for _ in 0..runs {
+                *outcomes.entry(run_once(&test, model, max_delay, new_cpu, new_mem_system)).or_insert(0) += 1;
+            }
+            for (outcome, count) in &outcomes {
+                let marker = if *outcome == test.relaxed { " relaxed" } else { "" };
+                println!("{:<4}{}: {}{}", model_name, outcome_name(&test, outcome), count, marker);
+            }
+            if outcomes.contains_key(&test.relaxed) {
+                relaxed_under.push(model_name);
+            }
+        }
+        println!("Relaxed outcome {} is forbidden under SC and {} under TSO, observed under: {}",
+                 outcome_name(&test, &test.relaxed),
+                 if test.tso_allows { "allowed" } else { "forbidden" },
+                 if relaxed_under.is_empty() { String::from("neither") } else { relaxed_under.join(" ") });
+        println!();
+    }
+}
@@ -1,20 +1,41 @@
 extern crate clap;
 extern crate rand;
+
+mod cache;
+mod hierarchy;
+mod prefetch;
+mod coherence;
+mod inorder;
+mod scoreboard;
+mod interpreter;
+mod litmus;
+mod pipetrace;
+mod timeline;
+mod stats;
+
 use clap::{Arg, App};
 use std::io::prelude::*;
 use std::io::BufReader;
 use std::fs::File;
-use std::collections::LinkedList;
+use std::collections::{HashMap, LinkedList};
 use std::fmt;
 use rand::Rng;
+use cache::{CacheConfig, ReplacementPolicy};
+use hierarchy::{DramConfig, Inclusion, MemoryConfig, MemorySystem};
+use prefetch::{Prefetcher, PrefetcherKind};
+use coherence::{CoherenceConfig, Protocol};
+use inorder::InOrderCore;
+use scoreboard::Scoreboard;
+use interpreter::Interpreter;
+use pipetrace::{PipelineTrace, Stage};
+use timeline::Timeline;
+use stats::Stats;
 
 const ROB_SIZE: usize = 32;
 const MEM_SIZE: usize = 52;
-const NUM_ALUS: usize = 3;
-const NUM_MULTS: usize = 2;
 const MAX_PREDICTIONS: usize = 1024;
-const DECODE_WIDTH: usize = 4;
-const COMMIT_WIDTH: usize = 4;
+// Hardware threads run separate programs so each gets its own range of cache addresses
+const THREAD_ADDRESS_SPACE: usize = 1 << 12;
 
 fn main() {
     let matches = App::new("My Simulator")
@@ -22,8 +43,9 @@ fn main() {
                           .author("Richard Boyd rb14427@my.bristol.ac.uk")
                           .about("Superscalar CPU simulator")
                           .arg(Arg::with_name("INPUT")
-                               .help("Sets the input file to use")
-                               .required(true)
+                               .help("Sets the input file to use, one per hardware thread")
+                               .required_unless("litmus")
+                               .multiple(true)
                                .index(1))
                           .arg(Arg::with_name("branch_prediction")
                                .short("p")
@@ -51,12 +73,435 @@ fn main() {
                                .help("Sets the number of instructions fetched per cycle")
                                .required(false)
                                .takes_value(true))
+                           .arg(Arg::with_name("decode_width")
+                               .long("decode-width")
+                               .help("Sets the number of instructions decoded per cycle")
+                               .required(false)
+                               .takes_value(true))
+                           .arg(Arg::with_name("rename_width")
+                               .long("rename-width")
+                               .help("Sets the number of destination registers renamed per cycle")
+                               .required(false)
+                               .takes_value(true))
+                           .arg(Arg::with_name("commit_width")
+                               .long("commit-width")
+                               .help("Sets the number of instructions committed per cycle")
+                               .required(false)
+                               .takes_value(true))
+                           .arg(Arg::with_name("queue_size")
+                               .long("queue-size")
+                               .help("Sets the number of fetched instructions the decode queue holds before fetch stalls")
+                               .required(false)
+                               .takes_value(true))
+                           .arg(Arg::with_name("lsq_size")
+                               .long("lsq-size")
+                               .help("Sets the number of loads, stores and fences each thread's load store queue holds, committed stores waiting for memory included (default unlimited)")
+                               .required(false)
+                               .takes_value(true))
+                           .arg(Arg::with_name("fetch_stages")
+                               .long("fetch-stages")
+                               .help("Sets the number of pipeline stages fetch takes")
+                               .required(false)
+                               .takes_value(true))
+                           .arg(Arg::with_name("decode_stages")
+                               .long("decode-stages")
+                               .help("Sets the number of pipeline stages decode takes")
+                               .required(false)
+                               .takes_value(true))
+                           .arg(Arg::with_name("rename_stages")
+                               .long("rename-stages")
+                               .help("Sets the number of pipeline stages rename takes")
+                               .required(false)
+                               .takes_value(true))
+                           .arg(Arg::with_name("dispatch_stages")
+                               .long("dispatch-stages")
+                               .help("Sets the number of pipeline stages between rename and an instruction being able to execute")
+                               .required(false)
+                               .takes_value(true))
                            .arg(Arg::with_name("numrs")
                                .short("r")
                                .long("reservations")
                                .help("Sets the number of reservation stations")
                                .required(false)
                                .takes_value(true))
+                           .arg(Arg::with_name("fu_config")
+                               .long("fu-config")
+                               .help("Reads the functional unit pool from a file, one \"<type> <count> <pipelined|unpipelined|interval:N> <op>:<latency> ...\" per line")
+                               .required(false)
+                               .takes_value(true))
+                           .arg(Arg::with_name("cdb_buses")
+                               .long("cdb-buses")
+                               .help("Sets the number of results broadcast on the common data bus per cycle, unlimited by default")
+                               .required(false)
+                               .takes_value(true))
+                           .arg(Arg::with_name("cdb_priority")
+                               .long("cdb-priority")
+                               .help("Sets which result wins the common data bus when there are too many (age|unit)")
+                               .required(false)
+                               .takes_value(true))
+                           .arg(Arg::with_name("rf_read_ports")
+                               .long("rf-read-ports")
+                               .help("Sets the number of register file read ports used at issue, unlimited by default")
+                               .required(false)
+                               .takes_value(true))
+                           .arg(Arg::with_name("rf_write_ports")
+                               .long("rf-write-ports")
+                               .help("Sets the number of register results written back per cycle, unlimited by default")
+                               .required(false)
+                               .takes_value(true))
+                           .arg(Arg::with_name("bypass_paths")
+                               .long("bypass-paths")
+                               .help("Sets the units that forward results straight to waiting instructions (e.g. alu,mult,branch,mem or none), all by default")
+                               .required(false)
+                               .takes_value(true))
+                           .arg(Arg::with_name("bypass_latency")
+                               .long("bypass-latency")
+                               .help("Sets the extra cycles a result without a bypass path takes to reach waiting instructions")
+                               .required(false)
+                               .takes_value(true))
+                           .arg(Arg::with_name("scheduler")
+                               .long("scheduler")
+                               .help("Sets whether reservation stations form one unified scheduler or one per functional unit type (unified|distributed)")
+                               .required(false)
+                               .takes_value(true))
+                           .arg(Arg::with_name("rs_alu")
+                               .long("rs-alu")
+                               .help("Sets the number of ALU reservation stations in a distributed scheduler")
+                               .required(false)
+                               .takes_value(true))
+                           .arg(Arg::with_name("rs_mult")
+                               .long("rs-mult")
+                               .help("Sets the number of multiplier reservation stations in a distributed scheduler")
+                               .required(false)
+                               .takes_value(true))
+                           .arg(Arg::with_name("rs_branch")
+                               .long("rs-branch")
+                               .help("Sets the number of branch reservation stations in a distributed scheduler")
+                               .required(false)
+                               .takes_value(true))
+                           .arg(Arg::with_name("select")
+                               .long("select")
+                               .help("Sets how ready reservation stations are picked for a functional unit (oldest|random|position)")
+                               .required(false)
+                               .takes_value(true))
+                           .arg(Arg::with_name("l1d_size")
+                               .long("l1d-size")
+                               .help("Sets the L1 data cache size in words")
+                               .required(false)
+                               .takes_value(true))
+                           .arg(Arg::with_name("l1d_line")
+                               .long("l1d-line")
+                               .help("Sets the L1 data cache line size in words")
+                               .required(false)
+                               .takes_value(true))
+                           .arg(Arg::with_name("l1d_assoc")
+                               .long("l1d-assoc")
+                               .help("Sets the L1 data cache associativity")
+                               .required(false)
+                               .takes_value(true))
+                           .arg(Arg::with_name("l1d_latency")
+                               .long("l1d-latency")
+                               .help("Sets the L1 data cache hit latency in cycles")
+                               .required(false)
+                               .takes_value(true))
+                           .arg(Arg::with_name("l1d_repl")
+                               .long("l1d-repl")
+                               .help("Sets the L1 data cache replacement policy
+                                      \nlru - Least recently used
+                                      \nplru - Tree pseudo LRU
+                                      \nrandom - Random
+                                      \nfifo - First in first out")
+                               .required(false)
+                               .takes_value(true))
+                           .arg(Arg::with_name("l1d_write")
+                               .long("l1d-write")
+                               .help("Sets the L1 data cache write hit policy
+                                      \nwb - Write back
+                                      \nwt - Write through")
+                               .required(false)
+                               .takes_value(true))
+                           .arg(Arg::with_name("l1d_no_alloc")
+                               .long("l1d-no-alloc")
+                               .help("Do not allocate a line in the L1 data cache on a write miss"))
+                           .arg(Arg::with_name("l1i_size")
+                               .long("l1i-size")
+                               .help("Sets the L1 instruction cache size in words")
+                               .required(false)
+                               .takes_value(true))
+                           .arg(Arg::with_name("l1i_line")
+                               .long("l1i-line")
+                               .help("Sets the L1 instruction cache line size in words")
+                               .required(false)
+                               .takes_value(true))
+                           .arg(Arg::with_name("l1i_assoc")
+                               .long("l1i-assoc")
+                               .help("Sets the L1 instruction cache associativity")
+                               .required(false)
+                               .takes_value(true))
+                           .arg(Arg::with_name("l1i_latency")
+                               .long("l1i-latency")
+                               .help("Sets the L1 instruction cache hit latency in cycles")
+                               .required(false)
+                               .takes_value(true))
+                           .arg(Arg::with_name("l1i_repl")
+                               .long("l1i-repl")
+                               .help("Sets the L1 instruction cache replacement policy (lru, plru, random, fifo)")
+                               .required(false)
+                               .takes_value(true))
+                           .arg(Arg::with_name("l2_size")
+                               .long("l2-size")
+                               .help("Sets the L2 cache size in words (0 disables it)")
+                               .required(false)
+                               .takes_value(true))
+                           .arg(Arg::with_name("l2_line")
+                               .long("l2-line")
+                               .help("Sets the L2 cache line size in words")
+                               .required(false)
+                               .takes_value(true))
+                           .arg(Arg::with_name("l2_assoc")
+                               .long("l2-assoc")
+                               .help("Sets the L2 cache associativity")
+                               .required(false)
+                               .takes_value(true))
+                           .arg(Arg::with_name("l2_latency")
+                               .long("l2-latency")
+                               .help("Sets the L2 cache hit latency in cycles")
+                               .required(false)
+                               .takes_value(true))
+                           .arg(Arg::with_name("l2_repl")
+                               .long("l2-repl")
+                               .help("Sets the L2 cache replacement policy (lru, plru, random, fifo)")
+                               .required(false)
+                               .takes_value(true))
+                           .arg(Arg::with_name("l3_size")
+                               .long("l3-size")
+                               .help("Sets the L3 cache size in words (0 disables it)")
+                               .required(false)
+                               .takes_value(true))
+                           .arg(Arg::with_name("l3_line")
+                               .long("l3-line")
+                               .help("Sets the L3 cache line size in words")
+                               .required(false)
+                               .takes_value(true))
+                           .arg(Arg::with_name("l3_assoc")
+                               .long("l3-assoc")
+                               .help("Sets the L3 cache associativity")
+                               .required(false)
+                               .takes_value(true))
+                           .arg(Arg::with_name("l3_latency")
+                               .long("l3-latency")
+                               .help("Sets the L3 cache hit latency in cycles")
+                               .required(false)
+                               .takes_value(true))
+                           .arg(Arg::with_name("l3_repl")
+                               .long("l3-repl")
+                               .help("Sets the L3 cache replacement policy (lru, plru, random, fifo)")
+                               .required(false)
+                               .takes_value(true))
+                           .arg(Arg::with_name("mshrs")
+                               .long("mshrs")
+                               .help("Sets the number of miss status holding registers in the L1 data cache")
+                               .required(false)
+                               .takes_value(true))
+                           .arg(Arg::with_name("prefetcher")
+                               .long("prefetcher")
+                               .help("Sets the L1 data cache prefetcher
+                                      \nnone - No prefetching
+                                      \nnext-line - Tagged next line prefetching
+                                      \nstride - PC indexed stride prefetching
+                                      \nstream - Stream buffer")
+                               .required(false)
+                               .takes_value(true))
+                           .arg(Arg::with_name("prefetch_degree")
+                               .long("prefetch-degree")
+                               .help("Sets the number of lines prefetched ahead, or the stream buffer depth")
+                               .required(false)
+                               .takes_value(true))
+                           .arg(Arg::with_name("stride_table")
+                               .long("stride-table")
+                               .help("Sets the number of entries in the stride prefetcher table")
+                               .required(false)
+                               .takes_value(true))
+                           .arg(Arg::with_name("inclusion")
+                               .long("inclusion")
+                               .help("Sets the inclusion policy between cache levels
+                                      \ninclusive - Lower levels hold every line held above them
+                                      \nexclusive - A line is held by at most one level
+                                      \nnine - Neither inclusive nor exclusive")
+                               .required(false)
+                               .takes_value(true))
+                           .arg(Arg::with_name("mem_latency")
+                               .long("mem-latency")
+                               .help("Sets the main memory latency in cycles when the row buffer misses")
+                               .required(false)
+                               .takes_value(true))
+                           .arg(Arg::with_name("mem_row_hit_latency")
+                               .long("mem-row-hit-latency")
+                               .help("Sets the main memory latency in cycles when the row buffer hits")
+                               .required(false)
+                               .takes_value(true))
+                           .arg(Arg::with_name("mem_banks")
+                               .long("mem-banks")
+                               .help("Sets the number of main memory banks")
+                               .required(false)
+                               .takes_value(true))
+                           .arg(Arg::with_name("mem_row_size")
+                               .long("mem-row-size")
+                               .help("Sets the main memory row size in words")
+                               .required(false)
+                               .takes_value(true))
+                           .arg(Arg::with_name("mem_bandwidth")
+                               .long("mem-bandwidth")
+                               .help("Sets the main memory bus bandwidth in words per cycle")
+                               .required(false)
+                               .takes_value(true))
+                           .arg(Arg::with_name("rename")
+                               .long("rename")
+                               .help("Sets the register renaming scheme
+                                      \nrob - Registers are renamed to reorder buffer entries
+                                      \nprf - Registers are renamed to a physical register file with a free list")
+                               .required(false)
+                               .takes_value(true))
+                           .arg(Arg::with_name("prf_size")
+                               .long("prf-size")
+                               .help("Sets the number of physical registers when renaming with a physical register file")
+                               .required(false)
+                               .takes_value(true))
+                           .arg(Arg::with_name("core")
+                               .long("core")
+                               .help("Sets the core model
+                                      \nooo - Out of order Tomasulo core
+                                      \ninorder - In order IF ID EX MEM WB pipeline
+                                      \nscoreboard - CDC 6600 style scoreboard without renaming
+                                      \ninterpreter - Reference interpreter without timing, contexts take turns running an instruction")
+                               .required(false)
+                               .takes_value(true))
+                           .arg(Arg::with_name("print_state")
+                               .long("print-state")
+                               .help("Prints the state of the in order or scoreboard core every cycle, the scoreboard as its status tables"))
+                           .arg(Arg::with_name("issue_width")
+                               .long("issue-width")
+                               .help("Sets the number of instructions the in order core moves through each stage per cycle, 1 is the classic scalar pipeline")
+                               .required(false)
+                               .takes_value(true))
+                           .arg(Arg::with_name("no_forwarding")
+                               .long("no-forwarding")
+                               .help("Disables forwarding in the in order core so dependent instructions wait for writeback"))
+                           .arg(Arg::with_name("threads")
+                               .long("threads")
+                               .help("Sets the number of hardware threads, thread i runs input file i modulo the number of files (default one per file)")
+                               .required(false)
+                               .takes_value(true))
+                          .arg(Arg::with_name("rob_sharing")
+                               .long("rob-sharing")
+                               .help("Sets how threads share the ROB
+                                      \nshared - Any thread can take any free entry (default)
+                                      \npartitioned - Each thread gets an equal share")
+                               .required(false)
+                               .takes_value(true))
+                          .arg(Arg::with_name("fetch_policy")
+                               .long("fetch-policy")
+                               .help("Sets which thread fetches each cycle
+                                      \nround-robin - Threads take turns (default)
+                                      \nicount - The thread with the fewest instructions in the front end and reservation stations")
+                               .required(false)
+                               .takes_value(true))
+                          .arg(Arg::with_name("cores")
+                               .long("cores")
+                               .help("Sets the number of out of order cores sharing memory, core i runs input file i modulo the number of files")
+                               .required(false)
+                               .takes_value(true))
+                          .arg(Arg::with_name("coherence")
+                               .long("coherence")
+                               .help("Sets the protocol keeping the L1 data caches coherent over a snooping bus
+                                      \nnone - No coherence traffic is modelled (default with one core)
+                                      \nmesi - Modified, exclusive, shared and invalid states (default with several cores)
+                                      \nmoesi - MESI with an owned state so dirty lines are shared without writing them back")
+                               .required(false)
+                               .takes_value(true))
+                          .arg(Arg::with_name("snoop_latency")
+                               .long("snoop-latency")
+                               .help("Sets the cycles a coherence bus transaction takes")
+                               .required(false)
+                               .takes_value(true))
+                          .arg(Arg::with_name("transfer_latency")
+                               .long("transfer-latency")
+                               .help("Sets the cycles to move a line from one L1 data cache to another")
+                               .required(false)
+                               .takes_value(true))
+                          .arg(Arg::with_name("coherence_trace")
+                               .long("coherence-trace")
+                               .help("Prints every coherence state change of a line"))
+                          .arg(Arg::with_name("consistency")
+                               .long("consistency")
+                               .help("Sets the memory consistency model the load store queues keep to
+                                      \nsc - Sequential consistency, loads wait behind older stores (default)
+                                      \ntso - Total store order, loads pass committed stores waiting in the store buffer")
+                               .required(false)
+                               .takes_value(true))
+                          .arg(Arg::with_name("litmus")
+                               .long("litmus")
+                               .help("Runs a litmus test under each consistency model instead of input files
+                                      \nmp - Message passing
+                                      \nsb - Store buffering
+                                      \niriw - Independent reads of independent writes
+                                      \nall - Every test")
+                               .required(false)
+                               .takes_value(true))
+                          .arg(Arg::with_name("litmus_runs")
+                               .long("litmus-runs")
+                               .help("Sets how many times each litmus test runs under each model")
+                               .required(false)
+                               .takes_value(true))
+                          .arg(Arg::with_name("litmus_delay")
+                               .long("litmus-delay")
+                               .help("Sets the most cycles a core waits before starting a litmus test, each run picking at random")
+                               .required(false)
+                               .takes_value(true))
+                          .arg(Arg::with_name("pipeline_trace")
+                               .long("pipeline-trace")
+                               .help("Writes when each instruction of the out of order core was fetched, decoded, dispatched, issued, completed, written back and committed or squashed in the Kanata format the Konata pipeline viewer opens, a multicore run writing a file per core with the core number appended")
+                               .required(false)
+                               .takes_value(true))
+                          .arg(Arg::with_name("trace_json")
+                               .long("trace-json")
+                               .help("Writes what each functional unit and memory unit of the out of order cores did every cycle, and ROB occupancy, as a Chrome Trace Event file for chrome://tracing or Perfetto, a cycle showing as a microsecond")
+                               .required(false)
+                               .takes_value(true))
+                          .arg(Arg::with_name("core_id_reg")
+                               .long("core-id-reg")
+                               .help("Sets a register each core starts with its core number in")
+                               .required(false)
+                               .takes_value(true))
+                          .arg(Arg::with_name("alone_ipc")
+                               .long("alone-ipc")
+                               .help("Sets a comma separated list of each thread's IPC when run alone, for weighted speedup and harmonic mean fairness")
+                               .required(false)
+                               .takes_value(true))
+                          .arg(Arg::with_name("checkpoints")
+                               .long("checkpoints")
+                               .help("Sets the number of rename map checkpoints, decode stalls on a branch when none are free")
+                               .required(false)
+                               .takes_value(true))
+                          .arg(Arg::with_name("profile")
+                               .long("profile")
+                               .help("Prints each input file annotated with how often each instruction committed, branch mispredictions, average cycles from issue to commit and L1 data cache misses, for the out of order cores")
+                               .required(false))
+                          .arg(Arg::with_name("stats_json")
+                               .long("stats-json")
+                               .help("Writes the statistics of every unit with the final registers and memory as JSON")
+                               .required(false)
+                               .takes_value(true))
+                          .arg(Arg::with_name("stats_csv")
+                               .long("stats-csv")
+                               .help("Writes the statistics of every unit with the final registers and memory as CSV rows of cycle, name and value")
+                               .required(false)
+                               .takes_value(true))
+                          .arg(Arg::with_name("stats_interval")
+                               .long("stats-interval")
+                               .help("Sets the cycles between snapshots of the statistics written with --stats-json or --stats-csv, which otherwise only cover the end of the run")
+                               .required(false)
+                               .takes_value(true))
                           .arg(Arg::with_name("v")
                                .short("v")
                                .multiple(true)
@@ -65,15 +510,29 @@ fn main() {
     
     let pred_type = matches.value_of("branch_prediction").unwrap_or("0").parse::<usize>().unwrap();
     println!("Prediction histroy size: {}", pred_type);
-    println!("Using input file: {}", matches.value_of("INPUT").unwrap());
-
+    let mut programs = Vec::new();
+    let mut sources = Vec::new();
+    for input in matches.values_of("INPUT").into_iter().flatten() {
+        println!("Using input file: {}", input);
 
-    let file = File::open(matches.value_of("INPUT").unwrap()).unwrap();
+        let file = File::open(input).unwrap();
 
-    let buf = BufReader::new(file);
-    let assembly: Vec<String> = buf.lines().map(|l| l.expect("Could not parse line")).collect();
+        let buf = BufReader::new(file);
+        let assembly: Vec<String> = buf.lines().map(|l| l.expect("Could not parse line")).collect();
 
-    let instructions = assemble(assembly);
+        programs.push(assemble(assembly.clone()));
+        sources.push((input.to_string(), assembly));
+    }
+    //Several programs go to separate cores in a multicore run and to hardware threads otherwise
+    let cores = matches.value_of("cores").unwrap_or("1").parse::<usize>().unwrap();
+    let default_threads = if cores > 1 || programs.is_empty() { 1 } else { programs.len() };
+    let threads = matches.value_of("threads").map_or(default_threads, |t| t.parse::<usize>().unwrap());
+    if threads == 0 {
+        panic!("At least one hardware thread is needed");
+    }
+    if cores > 1 && threads > 1 {
+        panic!("Multicore runs have one hardware thread per core");
+    }
 
     let mut memory: [u32; MEM_SIZE] = [0; MEM_SIZE];
 
@@ -101,424 +560,1708 @@ fn main() {
         }
     };
 
-    let f_width = matches.value_of("fetchwidth").unwrap_or("4").parse::<usize>().unwrap();
+    let pipeline = PipelineConfig {
+        fetch: matches.value_of("fetchwidth").unwrap_or("4").parse::<usize>().unwrap(),
+        decode: matches.value_of("decode_width").unwrap_or("4").parse::<usize>().unwrap(),
+        rename: matches.value_of("rename_width").unwrap_or("4").parse::<usize>().unwrap(),
+        commit: matches.value_of("commit_width").unwrap_or("4").parse::<usize>().unwrap(),
+        queue: matches.value_of("queue_size").unwrap_or("16").parse::<usize>().unwrap(),
+        lsq: matches.value_of("lsq_size").map(|size| size.parse::<usize>().unwrap()),
+        fetch_stages: matches.value_of("fetch_stages").unwrap_or("1").parse::<u32>().unwrap(),
+        decode_stages: matches.value_of("decode_stages").unwrap_or("1").parse::<u32>().unwrap(),
+        rename_stages: matches.value_of("rename_stages").unwrap_or("1").parse::<u32>().unwrap(),
+        dispatch_stages: matches.value_of("dispatch_stages").unwrap_or("1").parse::<u32>().unwrap(),
+    };
     let numrs = matches.value_of("numrs").unwrap_or("32").parse::<usize>().unwrap();
+    let schedulers = match matches.value_of("scheduler").unwrap_or("unified") {
+        "unified" => vec![(None, numrs)],
+        "distributed" => vec![
+            (Some(FUType::ALU), matches.value_of("rs_alu").unwrap_or("16").parse::<usize>().unwrap()),
+            (Some(FUType::Multiplier), matches.value_of("rs_mult").unwrap_or("8").parse::<usize>().unwrap()),
+            (Some(FUType::Branch), matches.value_of("rs_branch").unwrap_or("8").parse::<usize>().unwrap()),
+        ],
+        s => panic!("Unaccepted scheduler {}", s),
+    };
+    let scheduler_config = SchedulerConfig {
+        sizes: schedulers,
+        select: SelectPolicy::parse(matches.value_of("select").unwrap_or("position")),
+    };
+    let fu_pool = match matches.value_of("fu_config") {
+        Some(path) => FUConfig::load(path),
+        None => FUConfig::defaults(),
+    };
+    let new_exec_unit = || {
+        let cdb = CommonDataBus::new(
+            matches.value_of("cdb_buses").map(|b| b.parse::<usize>().unwrap()),
+            BusPriority::parse(matches.value_of("cdb_priority").unwrap_or("age")),
+        );
+        let ports = RegisterPorts::new(
+            matches.value_of("rf_read_ports").map(|p| p.parse::<usize>().unwrap()),
+            matches.value_of("rf_write_ports").map(|p| p.parse::<usize>().unwrap()),
+        );
+        let bypass = BypassNetwork::new(
+            matches.value_of("bypass_paths").map(BypassNetwork::parse_paths),
+            matches.value_of("bypass_latency").unwrap_or("1").parse::<u32>().unwrap(),
+        );
+        ExecUnit::new(&fu_pool, scheduler_config.clone(), cdb, ports, bypass)
+    };
 
-    let mut cpu = CPU::new(instructions, pred_type, f_width, numrs);
+    let l1i_config = cache_config(&matches, "l1i", CacheConfig {
+        size: 32,
+        line_size: 4,
+        assoc: 2,
+        hit_latency: 1,
+        replacement: ReplacementPolicy::Lru,
+        write_back: true,
+        write_allocate: true,
+    });
+    let mut l1d_config = cache_config(&matches, "l1d", CacheConfig {
+        size: 32,
+        line_size: 4,
+        assoc: 2,
+        hit_latency: 2,
+        replacement: ReplacementPolicy::Lru,
+        write_back: true,
+        write_allocate: true,
+    });
+    l1d_config.write_back = match matches.value_of("l1d_write").unwrap_or("wb") {
+        "wb" => true,
+        "wt" => false,
+        w => panic!("Unaccepted write policy {}", w),
+    };
+    l1d_config.write_allocate = !matches.is_present("l1d_no_alloc");
+    let l2_config = cache_config(&matches, "l2", CacheConfig {
+        size: 128,
+        line_size: 4,
+        assoc: 4,
+        hit_latency: 8,
+        replacement: ReplacementPolicy::Lru,
+        write_back: true,
+        write_allocate: true,
+    });
+    let l3_config = cache_config(&matches, "l3", CacheConfig {
+        size: 0,
+        line_size: 4,
+        assoc: 8,
+        hit_latency: 20,
+        replacement: ReplacementPolicy::Lru,
+        write_back: true,
+        write_allocate: true,
+    });
+    let dram_config = DramConfig {
+        row_hit_latency: matches.value_of("mem_row_hit_latency").unwrap_or("4").parse::<u32>().unwrap(),
+        row_miss_latency: matches.value_of("mem_latency").unwrap_or("10").parse::<u32>().unwrap(),
+        banks: matches.value_of("mem_banks").unwrap_or("4").parse::<usize>().unwrap(),
+        row_size: matches.value_of("mem_row_size").unwrap_or("16").parse::<usize>().unwrap(),
+        bandwidth: matches.value_of("mem_bandwidth").unwrap_or("2").parse::<usize>().unwrap(),
+    };
+    let inclusion = Inclusion::parse(matches.value_of("inclusion").unwrap_or("inclusive"));
+
+    let l2 = if l2_config.size > 0 { Some(l2_config) } else { None };
+    let l3 = if l3_config.size > 0 { Some(l3_config) } else { None };
+    if l2.is_none() && l3.is_some() {
+        panic!("An L3 cache needs an L2 cache");
+    }
+    let mshrs = matches.value_of("mshrs").unwrap_or("4").parse::<usize>().unwrap();
+    let prefetcher = Prefetcher::new(PrefetcherKind::parse(matches.value_of("prefetcher").unwrap_or("none")),
+                                     matches.value_of("prefetch_degree").unwrap_or("2").parse::<usize>().unwrap(),
+                                     matches.value_of("stride_table").unwrap_or("16").parse::<usize>().unwrap());
+    let default_protocol = if cores > 1 { "mesi" } else { "none" };
+    let coherence_config = CoherenceConfig {
+        protocol: Protocol::parse(matches.value_of("coherence").unwrap_or(default_protocol)),
+        snoop_latency: matches.value_of("snoop_latency").unwrap_or("2").parse::<u32>().unwrap(),
+        transfer_latency: matches.value_of("transfer_latency").unwrap_or("4").parse::<u32>().unwrap(),
+        trace: matches.is_present("coherence_trace"),
+    };
+    let memory_config = MemoryConfig {
+        l1i: l1i_config,
+        l1d: l1d_config,
+        l2,
+        l3,
+        inclusion,
+        dram: dram_config,
+        mshrs,
+        prefetcher,
+        coherence: coherence_config,
+    };
+    let mut mem_system = MemorySystem::new(cores, memory_config.clone());
 
-    let mut cycles = 0;
-    
      // Vary the output based on how many times the user used the "verbose" flag
     // (i.e. 'myprog -v -v -v' or 'myprog -vvv' vs 'myprog -v'
     let verbosity = matches.occurrences_of("v");
+    let print_state = matches.is_present("print_state");
+    let stats_interval = matches.value_of("stats_interval").map(|i| i.parse::<u64>().unwrap());
+    let mut statistics = if matches.is_present("stats_json") || matches.is_present("stats_csv") { Some(Stats::new(stats_interval)) } else { None };
+
+    let core = matches.value_of("core").unwrap_or("ooo");
+    let core_id_reg = matches.value_of("core_id_reg").map(|r| r.parse::<usize>().unwrap());
+    if let Some(reg) = core_id_reg {
+        if reg >= 32 {
+            panic!("Unaccepted register {}", reg);
+        }
+    }
+    let atomics = programs.iter().flatten().any(|i| matches!(*i, EncodedInstruction::Ll(_, _) | EncodedInstruction::Sc(_, _, _)));
+    if atomics && core != "ooo" && core != "interpreter" {
+        panic!("Load linked and store conditional need the out of order core or the interpreter");
+    }
+    //Hardware threads each get a private copy of memory so there is nothing for a reservation to guard
+    if atomics && threads > 1 {
+        panic!("Load linked and store conditional only work across cores, hardware threads do not share memory");
+    }
+    if core != "ooo" && matches.is_present("profile") {
+        panic!("Only the out of order core profiles instructions");
+    }
+    if core == "interpreter" {
+        //Contexts are laid out as the out of order cores and threads would run them
+        let memories = if cores > 1 { vec![memory] } else { vec![memory; threads] };
+        let mut interpreter = Interpreter::new(memories);
+        for core in 0..cores {
+            for t in 0..threads {
+                let mut registers = [0; 32];
+                if let Some(reg) = core_id_reg {
+                    registers[reg] = core as u32;
+                }
+                interpreter.add_context(programs[(core + t) % programs.len()].clone(), t, registers);
+            }
+        }
+        interpreter.run();
+        report_interpreter(&interpreter, cores, threads);
+        if let Some(ref mut stats) = statistics {
+            record_interpreter(stats, &interpreter, cores, threads);
+            write_stats(&matches, stats);
+        }
+        return;
+    }
+    if core != "ooo" && (threads > 1 || cores > 1) {
+        panic!("Only the out of order core runs more than one hardware thread or core");
+    }
+    match core {
+        "ooo" => (),
+        "inorder" => {
+            let width = matches.value_of("issue_width").unwrap_or("1").parse::<usize>().unwrap();
+            let forwarding = !matches.is_present("no_forwarding");
+            let mut core = InOrderCore::new(programs[0].clone(), pred_type, width, forwarding, &fu_pool);
+            let cycles = run(&mut core, &mut memory, &mut mem_system, verbosity, print_state, statistics.as_mut());
+
+            report_core(&memory, core.registers(), core.stats.retired, cycles, &core.branch_predictor);
+            let stats = &core.stats;
+            println!("In order issue width: {} forwarding: {}", width, forwarding);
+            println!("Issue cycles by group size: {:?}", stats.issue_widths);
+            println!("Data hazard stall cycles: {} load use stall cycles: {} forwarded operands: {}", stats.raw_stall_cycles, stats.load_use_stall_cycles, stats.forwarded_operands);
+            println!("Execute busy stall cycles: {} memory stall cycles: {}", stats.ex_busy_stall_cycles, stats.mem_stall_cycles);
+            println!("Branch flushes: {} flushed instructions: {} decode redirects: {}", stats.flushes, stats.flushed_instructions, stats.decode_redirects);
+            println!("Fetch stall cycles on instruction cache misses: {}", stats.icache_stall_cycles);
+            report_memory_system(&mem_system);
+            if let Some(ref mut stats) = statistics {
+                record_core(stats, &core, &memory, &mem_system, cycles);
+                write_stats(&matches, stats);
+            }
+            return;
+        },
+        "scoreboard" => {
+            let mut core = Scoreboard::new(programs[0].clone(), pred_type, pipeline.fetch, pipeline.queue, &fu_pool);
+            let cycles = run(&mut core, &mut memory, &mut mem_system, verbosity, print_state, statistics.as_mut());
+
+            report_core(&memory, core.registers(), core.stats.retired, cycles, &core.branch_predictor);
+            let stats = &core.stats;
+            println!("Issue stall cycles on a busy unit: {} WAW hazards: {} unresolved branches: {} empty buffer: {}", stats.structural_stall_cycles, stats.waw_stall_cycles, stats.branch_stall_cycles, stats.empty_cycles);
+            println!("Unit cycles waiting on RAW hazards: {} WAR hazards: {} memory: {}", stats.raw_stall_cycles, stats.war_stall_cycles, stats.mem_stall_cycles);
+            for (name, busy) in core.unit_busy_cycles() {
+                println!("{} busy cycles: {} utilisation: {:.2}", name, busy, busy as f32 / cycles as f32);
+            }
+            println!("Branch flushes: {} flushed instructions: {}", stats.flushes, stats.flushed_instructions);
+            println!("Fetch stall cycles on instruction cache misses: {}", stats.icache_stall_cycles);
+            report_memory_system(&mem_system);
+            if let Some(ref mut stats) = statistics {
+                record_core(stats, &core, &memory, &mem_system, cycles);
+                write_stats(&matches, stats);
+            }
+            return;
+        },
+        c => panic!("Unaccepted core {}", c),
+    }
+
+    let core_config = CoreConfig {
+        pred_type,
+        pipeline,
+        scheme: RenameScheme::parse(matches.value_of("rename").unwrap_or("rob")),
+        prf_size: matches.value_of("prf_size").unwrap_or("64").parse::<usize>().unwrap(),
+        checkpoints: matches.value_of("checkpoints").unwrap_or("8").parse::<usize>().unwrap(),
+        sharing: RobSharing::parse(matches.value_of("rob_sharing").unwrap_or("shared")),
+        fetch_policy: FetchPolicy::parse(matches.value_of("fetch_policy").unwrap_or("round-robin")),
+        consistency: Consistency::parse(matches.value_of("consistency").unwrap_or("sc")),
+    };
+
+    let front_end_stages = pipeline.fetch_stages + pipeline.decode_stages + pipeline.rename_stages + pipeline.dispatch_stages;
+
+    if let Some(test) = matches.value_of("litmus") {
+        let runs = matches.value_of("litmus_runs").unwrap_or("100").parse::<usize>().unwrap();
+        let max_delay = matches.value_of("litmus_delay").unwrap_or("50").parse::<u32>().unwrap();
+        let new_cpu = |program: Vec<EncodedInstruction>, core: usize, consistency: Consistency| {
+            let mut cpu = CPU::new(vec![program], new_exec_unit(), CoreConfig { consistency, ..core_config });
+            cpu.core = core;
+            cpu.threads[0].code_base = core * THREAD_ADDRESS_SPACE;
+            cpu
+        };
+        //The tests are multicore so their caches are kept coherent unless asked otherwise
+        let mut memory_config = memory_config;
+        if !matches.is_present("coherence") {
+            memory_config.coherence.protocol = Protocol::Mesi;
+        }
+        litmus::run(test, runs, max_delay, &new_cpu, &|cores| MemorySystem::new(cores, memory_config.clone()));
+        return;
+    }
+
+    let mut cpus: Vec<CPU> = (0..cores).map(|core| {
+        let thread_programs = (0..threads).map(|t| programs[(core + t) % programs.len()].clone()).collect();
+        let mut cpu = CPU::new(thread_programs, new_exec_unit(), core_config);
+        cpu.core = core;
+        if cores > 1 {
+            //Cores running the same program share its instructions in the L2
+            cpu.threads[0].code_base = (core % programs.len()) * THREAD_ADDRESS_SPACE;
+        }
+        if let Some(reg) = core_id_reg {
+            for thread in &mut cpu.threads {
+                thread.registers.set(reg, core as u32);
+            }
+        }
+        if let Some(path) = matches.value_of("pipeline_trace") {
+            let path = if cores > 1 { format!("{}.{}", path, core) } else { path.to_string() };
+            cpu.trace = Some(PipelineTrace::new(&path, threads));
+        }
+        if matches.is_present("trace_json") {
+            let mut tracks: Vec<String> = cpu.exec_unit.func_units.iter().enumerate().map(|(i, fu)| format!("FU {} ({:?})", i, fu.fu_type)).collect();
+            tracks.push(String::from("Memory unit"));
+            cpu.timeline = Some(Timeline::new(core, tracks));
+        }
+        cpu
+    }).collect();
+    //Each thread is its own process with a private copy of the initial memory, cores all share the first
+    let mut memories = vec![memory; threads];
+
+    let mut cycles: u64 = 0;
 
     loop {
-        commit(&mut cpu);
-        writeback(&mut cpu);
-        execute(&mut cpu, &mut memory);
-        decode(&mut cpu);
-        fetch(&mut cpu);
+        for cpu in cpus.iter_mut().filter(|cpu| !cpu.finished()) {
+            step(cpu, &mut memories, &mut mem_system);
+        }
 
         cycles += 1;
+        mem_system.tick();
+
+        for cpu in &mut cpus {
+            for t in 0..threads {
+                if cpu.threads[t].finished_at.is_none() && cpu.thread_finished(t) {
+                    cpu.threads[t].finished_at = Some(cycles);
+                }
+            }
+        }
 
         if verbosity >= 1 {
             println!("Cycle {} Complete", cycles);
-            println!("CPU: {:?}", cpu.registers.gprs);
+            for thread in cpus.iter().flat_map(|cpu| cpu.threads.iter()) {
+                println!("CPU: {:?}", thread.registers.gprs);
+            }
             println!("");
         }
         
         if verbosity >= 2 {
             
-            for i in memory.iter() {
-                print!("{} ", i);
+            for memory in &memories {
+                for i in memory.iter() {
+                    print!("{} ", i);
+                }
+                println!();
             }
-            println!("");
         }
         if verbosity >= 3 {
-            println!("CPU: {:?}", cpu);
+            for cpu in &cpus {
+                println!("CPU: {:?}", cpu);
+            }
         }
 
-        if cpu.finished() {
+        if cpus.iter().all(|cpu| cpu.finished()) {
             break;
         }
+
+        if let Some(ref mut stats) = statistics {
+            if stats.due(cycles) {
+                record_cpus(stats, &cpus, &memories, &mem_system, cycles);
+            }
+        }
+    }
+
+    if let Some(path) = matches.value_of("trace_json") {
+        timeline::write(path, cpus.iter_mut().filter_map(|cpu| cpu.timeline.take()).collect());
+    }
+
+    if cores > 1 {
+        report_cores(&cpus, &memories[0], cycles, front_end_stages);
+    } else {
+        let cpu = &cpus[0];
+        if threads == 1 {
+            report_core(&memories[0], &cpu.threads[0].registers.gprs, cpu.rob.instructions_committed as u64, cycles, &cpu.branch_predictor);
+        } else {
+            let alone_ipc = matches.value_of("alone_ipc").map(|list| list.split(',').map(|ipc| ipc.parse::<f32>().unwrap()).collect());
+            report_threads(cpu, &memories, cycles, alone_ipc);
+        }
+        report_cpu(cpu, cycles, front_end_stages);
+    }
+
+    report_memory_system(&mem_system);
+    report_top_down(&cpus);
+    if matches.is_present("profile") {
+        report_profile(&cpus, &sources);
+    }
+    if let Some(ref mut stats) = statistics {
+        record_cpus(stats, &cpus, &memories, &mem_system, cycles);
+        write_stats(&matches, stats);
+    }
+}
+
+// The core models other than the Tomasulo core, which all run on the shared loop below
+trait Core: fmt::Debug {
+    fn cycle(&mut self, memory: &mut [u32; MEM_SIZE], mem_system: &mut MemorySystem);
+    fn finished(&self) -> bool;
+    fn registers(&self) -> &[u32; 32];
+    fn record(&self, stats: &mut Stats);
+}
+
+fn run(core: &mut dyn Core, memory: &mut [u32; MEM_SIZE], mem_system: &mut MemorySystem, verbosity: u64, print_state: bool, mut stats: Option<&mut Stats>) -> u64 {
+    let mut cycles = 0;
+    loop {
+        core.cycle(memory, mem_system);
+
+        cycles += 1;
+        mem_system.tick();
+
+        if verbosity >= 1 {
+            println!("Cycle {} Complete", cycles);
+            println!("CPU: {:?}", core.registers());
+            println!();
+        }
+        if verbosity >= 2 {
+            for i in memory.iter() {
+                print!("{} ", i);
+            }
+            println!();
+        }
+        if verbosity >= 3 || print_state {
+            println!("{:?}", core);
+            println!();
+        }
+
+        if core.finished() {
+            return cycles;
+        }
+
+        if let Some(ref mut stats) = stats {
+            if stats.due(cycles) {
+                record_core(stats, core, memory, mem_system, cycles);
+            }
+        }
     }
+}
 
+// The results and headline figures every core model reports
+fn report_core(memory: &[u32; MEM_SIZE], registers: &[u32; 32], instructions: u64, cycles: u64, branch_predictor: &BranchPredictor) {
     for i in 0..52 {
         print!("{} ", memory[i]);
     }
     println!("");
     
-    println!("Registers Final Values: {:?}", cpu.registers.gprs);
-    println!("Instructions executed: {}", cpu.rob.instructions_committed);
+    println!("Registers Final Values: {:?}", registers);
+    println!("Instructions executed: {}", instructions);
     println!("Number of cycles: {}", cycles);
-    println!("Instructions per cycle: {:.2}", (cpu.rob.instructions_committed as f32)  / (cycles as f32));
-    println!("Branch prediction accuracy: {:.2}", cpu.branch_predictor.accuracy());
+    println!("Instructions per cycle: {:.2}", (instructions as f32)  / (cycles as f32));
+    println!("Branch prediction accuracy: {:.2}", branch_predictor.accuracy());
 }
 
-fn fetch(cpu: &mut CPU) {
-
-    match cpu.fetch_unit.reset {
-        true => {
-            cpu.fetch_unit.reset = false;
-        },
-        false => {
-            for _ in 0..cpu.fetch_unit.width {
-                let inst = cpu.fetch_unit.get_instruction();
-                match inst {
-                    EncodedInstruction::Halt => (),
-                    _ => {
-                        cpu.decode_unit.add_instruction(inst, cpu.fetch_unit.pc);
-                        cpu.fetch_unit.pc += 1;
-                    }
-                }
-            }
+fn report_interpreter(interpreter: &Interpreter, cores: usize, threads: usize) {
+    for memory in &interpreter.memories {
+        for i in memory.iter() {
+            print!("{} ", i);
+        }
+        println!();
+    }
+    for context in 0..cores * threads {
+        if cores > 1 {
+            println!("Core {}", context);
+        } else if threads > 1 {
+            println!("Thread {}", context);
+        }
+        println!("Registers Final Values: {:?}", interpreter.registers(context));
+        println!("Instructions executed: {}", interpreter.executed(context));
+    }
+}
+
+// The out of order core's pipeline statistics, summed over its hardware threads
+fn report_cpu(cpu: &CPU, cycles: u64, front_end_stages: u32) {
+    let registers: Vec<&Registers> = cpu.threads.iter().map(|t| &t.registers).collect();
+    if cpu.threads[0].registers.scheme == RenameScheme::Prf {
+        println!("Rename stalls on an empty free list: {}", registers.iter().map(|r| r.free_list_stalls).sum::<u64>());
+    }
+    println!("Decode stall cycles on exhausted checkpoints: {}", registers.iter().map(|r| r.checkpoint_stalls).sum::<u64>());
+    for scheduler in &cpu.exec_unit.schedulers {
+        let stats = &scheduler.stats;
+        println!("{} reservation stations: {} average occupancy: {:.2} full stalls: {}", scheduler.name(), scheduler.end - scheduler.start, stats.occupancy_sum as f32 / cycles as f32, stats.full_stalls);
+        println!("{} reservation stations selected: {} ready but not selected: {}", scheduler.name(), stats.selected, stats.ready_not_selected);
+    }
+    for (i, fu) in cpu.exec_unit.func_units.iter().enumerate() {
+        println!("FU {} ({:?}) operations: {} busy cycles: {} utilisation: {:.2} writeback stall cycles: {}", i, fu.fu_type, fu.operations, fu.busy_cycles, fu.busy_cycles as f32 / cycles as f32, fu.writeback_stall_cycles);
+    }
+    let buses = &cpu.exec_unit.cdb.stats;
+    println!("Result bus broadcasts: {} writeback conflicts: {} cycles with conflicts: {}", buses.broadcasts, buses.conflicts, buses.conflict_cycles);
+    let ports = &cpu.exec_unit.ports.stats;
+    println!("Register file read port stall cycles: {} write port conflicts: {} cycles with write port conflicts: {}", ports.read_stall_cycles, ports.write_conflicts, ports.write_stall_cycles);
+    if cpu.exec_unit.bypass.paths.is_some() {
+        println!("Results delayed without a bypass path: {}", cpu.exec_unit.bypass.delayed);
+    }
+    let fetch_units: Vec<&FetchUnit> = cpu.threads.iter().map(|t| &t.fetch_unit).collect();
+    let decode_units: Vec<&DecodeUnit> = cpu.threads.iter().map(|t| &t.decode_unit).collect();
+    let fetch_blocks = fetch_units.iter().map(|f| f.fetch_blocks).sum::<u64>();
+    println!("Fetch blocks: {} average block size: {:.2}", fetch_blocks, fetch_units.iter().map(|f| f.instructions_fetched).sum::<u64>() as f32 / fetch_blocks as f32);
+    println!("Fetch stall cycles on instruction cache misses: {}", fetch_units.iter().map(|f| f.icache_stall_cycles).sum::<u64>());
+    println!("Fetch stall cycles on a full instruction queue: {}", fetch_units.iter().map(|f| f.queue_full_cycles).sum::<u64>());
+    println!("Front end stages: {} redirects: {}", front_end_stages, fetch_units.iter().map(|f| f.redirects).sum::<u64>());
+    println!("Decode stall cycles: {} empty queue cycles: {} rename width stalls: {}", decode_units.iter().map(|d| d.stall_cycles).sum::<u64>(), decode_units.iter().map(|d| d.empty_cycles).sum::<u64>(), decode_units.iter().map(|d| d.rename_width_stalls).sum::<u64>());
+    println!("Commit stall cycles: {} empty ROB cycles: {}", cpu.rob.stall_cycles, cpu.rob.empty_cycles);
+    if cpu.threads[0].lsq.model == Consistency::Tso {
+        let lsqs: Vec<&LSQ> = cpu.threads.iter().map(|t| &t.lsq).collect();
+        println!("Loads passing buffered stores: {} forwarded from the store buffer: {}", lsqs.iter().map(|l| l.bypassed_loads).sum::<u64>(), lsqs.iter().map(|l| l.forwarded_loads).sum::<u64>());
+    }
+}
+
+// Commit slots of every core by where they went, back end causes indented under their total
+fn report_top_down(cpus: &[CPU]) {
+    let mut top_down = TopDown::default();
+    for cpu in cpus {
+        top_down.merge(&cpu.top_down);
+    }
+    let slots = top_down.slots();
+    println!("{:<20}{:>10}{:>8}", "Commit slots", slots, "share");
+    for &(category, count) in &[("Retiring", top_down.retiring),
+                                ("Bad speculation", top_down.bad_speculation),
+                                ("Front end bound", top_down.front_end),
+                                ("Back end bound", top_down.back_end()),
+                                ("  ROB full", top_down.rob_full),
+                                ("  RS full", top_down.rs_full),
+                                ("  LSQ full", top_down.lsq_full),
+                                ("  Memory", top_down.memory),
+                                ("  Divider", top_down.divider),
+                                ("  Core", top_down.core)] {
+        println!("{:<20}{:>10}{:>7.1}%", category, count, 100.0 * count as f32 / slots as f32);
+    }
+}
+
+// Each program's source with what its instructions did across every thread that ran it
+fn report_profile(cpus: &[CPU], sources: &[(String, Vec<String>)]) {
+    for (p, (input, lines)) in sources.iter().enumerate() {
+        let mut profile = vec![PcProfile::default(); lines.len()];
+        for cpu in cpus {
+            for t in (0..cpu.threads.len()).filter(|t| (cpu.core + t) % sources.len() == p) {
+                for (total, counts) in profile.iter_mut().zip(cpu.profile(t)) {
+                    total.merge(&counts);
+                }
+            }
+        }
+        let executed: u64 = profile.iter().map(|counts| counts.executed).sum();
+        println!("Profile of {}", input);
+        println!("{:>4}{:>10}{:>8}{:>14}{:>10}{:>12}  source", "pc", "executed", "share", "mispredicted", "latency", "L1D misses");
+        for (pc, (line, counts)) in lines.iter().zip(&profile).enumerate() {
+            let share = if executed == 0 { 0.0 } else { 100.0 * counts.executed as f32 / executed as f32 };
+            let blank_if_zero = |count: u64| if count == 0 { String::new() } else { count.to_string() };
+            let latency = if counts.timed == 0 { String::new() } else { format!("{:.1}", counts.latency as f32 / counts.timed as f32) };
+            println!("{:>4}{:>10}{:>7.1}%{:>14}{:>10}{:>12}  {}", pc, counts.executed, share, blank_if_zero(counts.mispredicted), latency, blank_if_zero(counts.l1d_misses), line);
+        }
+        println!();
+    }
+}
+
+// The shared memory, then each core's results and statistics and the totals across cores
+fn report_cores(cpus: &[CPU], memory: &[u32; MEM_SIZE], cycles: u64, front_end_stages: u32) {
+    for i in memory.iter() {
+        print!("{} ", i);
+    }
+    println!();
+
+    for (c, cpu) in cpus.iter().enumerate() {
+        let instructions = cpu.rob.instructions_committed;
+        let core_cycles = cpu.threads[0].finished_at.unwrap_or(cycles);
+        println!("Core {}", c);
+        println!("Registers Final Values: {:?}", cpu.threads[0].registers.gprs);
+        println!("Core {} instructions executed: {} cycles: {} IPC: {:.2}", c, instructions, core_cycles, instructions as f32 / core_cycles as f32);
+        println!("Core {} branch prediction accuracy: {:.2}", c, cpu.branch_predictor.accuracy());
+        report_cpu(cpu, core_cycles, front_end_stages);
+    }
+
+    let instructions: usize = cpus.iter().map(|cpu| cpu.rob.instructions_committed).sum();
+    let predictions: u32 = cpus.iter().map(|cpu| cpu.branch_predictor.total_predictions).sum();
+    let correct: u32 = cpus.iter().map(|cpu| cpu.branch_predictor.total_correct).sum();
+    println!("Instructions executed: {}", instructions);
+    println!("Number of cycles: {}", cycles);
+    println!("Instructions per cycle: {:.2}", (instructions as f32)  / (cycles as f32));
+    println!("Branch prediction accuracy: {:.2}", correct as f32 / predictions as f32);
+}
+
+// Each thread's results and IPC over the cycles it took, then throughput and fairness across threads
+fn report_threads(cpu: &CPU, memories: &[[u32; MEM_SIZE]], cycles: u64, alone_ipc: Option<Vec<f32>>) {
+    let mut ipcs = Vec::new();
+    for (t, thread) in cpu.threads.iter().enumerate() {
+        let instructions = cpu.rob.thread_committed[t];
+        let thread_cycles = thread.finished_at.unwrap_or(cycles);
+        let ipc = instructions as f32 / thread_cycles as f32;
+        ipcs.push(ipc);
+
+        println!("Thread {}", t);
+        for i in memories[t].iter() {
+            print!("{} ", i);
+        }
+        println!();
+        println!("Registers Final Values: {:?}", thread.registers.gprs);
+        println!("Thread {} instructions executed: {} cycles: {} IPC: {:.2}", t, instructions, thread_cycles, ipc);
+    }
+
+    let instructions = cpu.rob.instructions_committed;
+    println!("Instructions executed: {}", instructions);
+    println!("Number of cycles: {}", cycles);
+    println!("Instructions per cycle: {:.2}", (instructions as f32)  / (cycles as f32));
+    println!("Branch prediction accuracy: {:.2}", cpu.branch_predictor.accuracy());
+
+    let sum: f32 = ipcs.iter().sum();
+    let sum_squares: f32 = ipcs.iter().map(|ipc| ipc * ipc).sum();
+    let min = ipcs.iter().cloned().fold(f32::INFINITY, f32::min);
+    let max = ipcs.iter().cloned().fold(0.0, f32::max);
+    println!("Jain's fairness index: {:.2} min/max thread IPC: {:.2}", sum * sum / (ipcs.len() as f32 * sum_squares), min / max);
+    if let Some(alone) = alone_ipc {
+        if alone.len() != ipcs.len() {
+            panic!("Expected {} alone IPCs, got {}", ipcs.len(), alone.len());
+        }
+        let weighted: f32 = ipcs.iter().zip(alone.iter()).map(|(ipc, alone)| ipc / alone).sum();
+        let hmean = ipcs.len() as f32 / ipcs.iter().zip(alone.iter()).map(|(ipc, alone)| alone / ipc).sum::<f32>();
+        println!("Weighted speedup: {:.2} harmonic mean of relative IPCs: {:.2}", weighted, hmean);
+    }
+}
+
+fn report_memory_system(mem_system: &MemorySystem) {
+    for level in &mem_system.levels {
+        let stats = &level.cache.stats;
+        println!("{} reads: {} writes: {}", level.name, stats.reads, stats.writes);
+        println!("{} hits: {} misses: {} evictions: {} writebacks: {}", level.name, stats.hits, stats.misses, stats.evictions, stats.writebacks);
+        println!("{} hit rate: {:.2}", level.name, stats.hit_rate());
+    }
+    for (c, core) in mem_system.cores.iter().enumerate() {
+        let prefix = if mem_system.cores.len() == 1 { String::new() } else { format!("Core {} ", c) };
+        let mshrs = &core.l1d_mshrs.stats;
+        println!("{}L1D MSHR primary misses: {} secondary misses: {}", prefix, mshrs.primary_misses, mshrs.secondary_misses);
+        println!("{}L1D MSHR full stall cycles: {}", prefix, mshrs.full_stall_cycles);
+        println!("{}Memory level parallelism: {:.2}", prefix, mshrs.memory_level_parallelism());
+        if core.prefetcher.kind != PrefetcherKind::None {
+            let prefetches = &core.prefetcher.stats;
+            println!("{}Prefetches issued: {} useful: {} late: {} dropped: {}", prefix, prefetches.issued, prefetches.useful, prefetches.late, prefetches.dropped);
+            println!("{}Prefetch accuracy: {:.2} coverage: {:.2} timeliness: {:.2}", prefix, prefetches.accuracy(), prefetches.coverage(mshrs.primary_misses), prefetches.timeliness());
+        }
+    }
+    let coherence = &mem_system.coherence;
+    if coherence.enabled() {
+        let stats = &coherence.stats;
+        println!("Coherence protocol: {}", coherence.config.protocol.name());
+        println!("Coherence messages: {} BusRd: {} BusRdX: {} BusUpgr: {} BusWr: {}", stats.messages(), stats.bus_reads, stats.bus_read_exclusives, stats.upgrades, stats.bus_writes);
+        println!("Cache to cache transfers: {} flushes: {} silent upgrades: {}", stats.cache_to_cache, stats.flushes, stats.silent_upgrades);
+        let received: Vec<String> = coherence.invalidations_received.iter().map(|i| i.to_string()).collect();
+        println!("Invalidations: {} received per core: {}", stats.invalidations, received.join(" "));
+        let mut lines: Vec<(&usize, &u64)> = coherence.invalidations_by_line.iter().collect();
+        lines.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        for &(line, count) in lines.iter().take(4) {
+            println!("Line {} (words {}-{}) invalidated {} times", line, line * mem_system.l1d_line_size(), (line + 1) * mem_system.l1d_line_size() - 1, count);
+        }
+    }
+    let dram = &mem_system.dram.stats;
+    println!("DRAM reads: {} writes: {}", dram.reads, dram.writes);
+    println!("DRAM row hits: {} row misses: {} row hit rate: {:.2}", dram.row_hits, dram.row_misses, dram.row_hit_rate());
+    println!("DRAM bank stall cycles: {} bus stall cycles: {}", dram.bank_stall_cycles, dram.bus_stall_cycles);
+}
+
+// Cores share one memory while hardware threads each get their own
+fn record_memories(stats: &mut Stats, memories: &[[u32; MEM_SIZE]]) {
+    if memories.len() == 1 {
+        stats.values("memory", &memories[0]);
+    } else {
+        for (t, memory) in memories.iter().enumerate() {
+            stats.values(&format!("core0.thread{}.memory", t), memory);
+        }
+    }
+}
+
+fn record_cpus(stats: &mut Stats, cpus: &[CPU], memories: &[[u32; MEM_SIZE]], mem_system: &MemorySystem, cycles: u64) {
+    stats.counter("cycles", cycles);
+    for cpu in cpus {
+        stats.enter(&format!("core{}", cpu.core));
+        cpu.record(stats);
+        stats.leave();
+    }
+    mem_system.record(stats);
+    record_memories(stats, memories);
+    stats.snapshot(cycles);
+}
+
+// The other core models, named as the out of order core's first thread would be
+fn record_core(stats: &mut Stats, core: &dyn Core, memory: &[u32; MEM_SIZE], mem_system: &MemorySystem, cycles: u64) {
+    stats.counter("cycles", cycles);
+    stats.enter("core0");
+    core.record(stats);
+    stats.values("thread0.registers", core.registers());
+    stats.leave();
+    mem_system.record(stats);
+    stats.values("memory", memory);
+    stats.snapshot(cycles);
+}
+
+// The interpreter has no timing so its one snapshot is at cycle 0
+fn record_interpreter(stats: &mut Stats, interpreter: &Interpreter, cores: usize, threads: usize) {
+    for core in 0..cores {
+        stats.enter(&format!("core{}", core));
+        let contexts = core * threads..(core + 1) * threads;
+        stats.counter("instructions", contexts.clone().map(|context| interpreter.executed(context)).sum());
+        for context in contexts {
+            stats.enter(&format!("thread{}", context - core * threads));
+            stats.counter("instructions", interpreter.executed(context));
+            stats.values("registers", interpreter.registers(context));
+            stats.leave();
+        }
+        stats.leave();
+    }
+    record_memories(stats, &interpreter.memories);
+    stats.snapshot(0);
+}
+
+fn write_stats(matches: &clap::ArgMatches, stats: &Stats) {
+    if let Some(path) = matches.value_of("stats_json") {
+        stats.write_json(path);
+    }
+    if let Some(path) = matches.value_of("stats_csv") {
+        stats.write_csv(path);
+    }
+}
+
+fn cache_config(matches: &clap::ArgMatches, prefix: &str, defaults: CacheConfig) -> CacheConfig {
+    let value = |field: &str| matches.value_of(format!("{}_{}", prefix, field));
+    CacheConfig {
+        size: value("size").map_or(defaults.size, |v| v.parse::<usize>().unwrap()),
+        line_size: value("line").map_or(defaults.line_size, |v| v.parse::<usize>().unwrap()),
+        assoc: value("assoc").map_or(defaults.assoc, |v| v.parse::<usize>().unwrap()),
+        hit_latency: value("latency").map_or(defaults.hit_latency, |v| v.parse::<u32>().unwrap()),
+        replacement: value("repl").map_or(defaults.replacement, ReplacementPolicy::parse),
+        write_back: defaults.write_back,
+        write_allocate: defaults.write_allocate,
+    }
+}
+
+// One cycle of the out of order core, stages running back to front so each sees last cycle's state
+fn step(cpu: &mut CPU, memories: &mut [[u32; MEM_SIZE]], mem_system: &mut MemorySystem) {
+    commit(cpu);
+    writeback(cpu);
+    execute(cpu, memories, mem_system);
+    decode(cpu);
+    fetch(cpu, mem_system);
+
+    cpu.cycle += 1;
+    if let Some(ref mut trace) = cpu.trace {
+        for (t, thread) in cpu.threads.iter().enumerate() {
+            trace.front_end(t, &thread.decode_unit.in_flight());
+        }
+        trace.cycle = cpu.cycle;
+    }
+    let CPU { ref mut timeline, ref rob, ref threads, .. } = *cpu;
+    if let Some(ref mut timeline) = *timeline {
+        let occupancy: Vec<usize> = (0..threads.len()).map(|t| rob.len(t)).collect();
+        timeline.occupancy(&occupancy);
+        timeline.cycle = cpu.cycle;
+    }
+}
+
+fn fetch(cpu: &mut CPU, mem_system: &mut MemorySystem) {
+    //Every thread's redirect bubbles and instruction cache misses run down, then one thread fetches
+    let mut ready = Vec::new();
+    for t in 0..cpu.threads.len() {
+        let thread = &mut cpu.threads[t];
+        if thread.fetch_unit.reset {
+            thread.fetch_unit.reset = false;
+            continue;
+        }
+        if thread.fetch_unit.stall_cycles > 0 {
+            thread.fetch_unit.stall_cycles -= 1;
+            thread.fetch_unit.icache_stall_cycles += 1;
+            continue;
+        }
+        if thread.fetch_unit.finished() {
+            continue;
+        }
+        if thread.decode_unit.is_full() {
+            thread.fetch_unit.queue_full_cycles += 1;
+            continue;
+        }
+        ready.push(t);
+    }
+    if let Some(thread) = cpu.select_fetch(&ready) {
+        fetch_block(cpu, thread, mem_system);
+    }
+    cpu.priority = (cpu.priority + 1) % cpu.threads.len();
+}
+
+fn fetch_block(cpu: &mut CPU, thread: usize, mem_system: &mut MemorySystem) {
+    let CPU { ref mut threads, ref branch_predictor, ref mut trace, core, .. } = *cpu;
+    let Thread { ref mut fetch_unit, ref mut decode_unit, code_base, .. } = threads[thread];
+
+    //Fetch blocks are line aligned so one instruction cache access covers the block
+    let line_size = mem_system.l1i_line_size();
+    let line = fetch_unit.pc / line_size;
+    if fetch_unit.filled_line != Some(line) {
+        let stall = mem_system.access_instruction(core, code_base + fetch_unit.pc);
+        if stall > 0 {
+            fetch_unit.stall_cycles = stall - 1;
+            fetch_unit.icache_stall_cycles += 1;
+            fetch_unit.filled_line = Some(line);
+            return;
+        }
+    }
+    fetch_unit.filled_line = None;
+    fetch_unit.fetch_blocks += 1;
+
+    for _ in 0..fetch_unit.width {
+        let inst = fetch_unit.get_instruction();
+        match inst {
+            EncodedInstruction::Halt => break,
+            _ => {
+                let pc = fetch_unit.pc;
+                let seq = fetch_unit.instructions_fetched;
+                decode_unit.add_instruction(inst, pc, seq);
+                if let Some(ref mut trace) = *trace {
+                    trace.fetch(thread, seq, pc, format!("{:?}", inst));
+                }
+                fetch_unit.pc += 1;
+                fetch_unit.instructions_fetched += 1;
+
+                let taken = match inst {
+                    EncodedInstruction::J(_) => true,
+                    EncodedInstruction::Beq(_, _, _) |
+                    EncodedInstruction::Beqz(_, _) |
+                    EncodedInstruction::Blt(_, _, _) |
+                    EncodedInstruction::Bgt(_, _, _) => branch_predictor.predicts_taken(pc),
+                    _ => false,
+                };
+                if taken || fetch_unit.pc % line_size == 0 || decode_unit.is_full() {
+                    break;
+                }
+            }
         }
     }
 }
 
 fn decode(cpu: &mut CPU) {
-    for _ in 0..DECODE_WIDTH {
-        let possible_instruction = cpu.decode_unit.get_next_instruction();
+    cpu.exec_unit.ports.new_cycle();
+    for thread in &mut cpu.threads {
+        thread.decode_unit.advance();
+        thread.registers.new_cycle();
+    }
+    if let Some(ref mut trace) = cpu.trace {
+        for (t, thread) in cpu.threads.iter().enumerate() {
+            for &(seq, _, _) in &thread.decode_unit.instruction_q {
+                trace.decode(t, seq);
+            }
+        }
+    }
+    //Threads take turns at the decode and rename bandwidth, moving on when one stalls
+    let mut decoded = 0;
+    let mut renamed = 0;
+    for thread in cpu.rotation() {
+        decode_thread(cpu, thread, &mut decoded, &mut renamed);
+    }
+
+    //now dispatch
+    let issued = cpu.exec_unit.dispatch(&cpu.rob);
+    for &rob_entry in &issued {
+        cpu.rob.buffer[rob_entry].issued = Some(cpu.cycle);
+    }
+    if let Some(ref mut trace) = cpu.trace {
+        for rob_entry in issued {
+            trace.stage(rob_entry, Stage::Issue);
+        }
+    }
+
+    //Now check the LSQs if something can be executed
+    for thread in cpu.rotation() {
+        if cpu.exec_unit.mem_unit.can_accept() {
+            let head = cpu.rob.commit[thread];
+            let drained = cpu.exec_unit.mem_unit.stores_drained(thread);
+            if let Some(i) = cpu.threads[thread].lsq.get_next_instruction(head, drained) {
+                match i.op {
+                    //A fence completes like a store once it leaves the queue, having nothing to write back
+                    LSQOp::Fence => cpu.rob.insert(i.rob_entry, ExecResult::Store),
+                    _ => { cpu.exec_unit.mem_unit.dispatch(i); },
+                }
+                if i.op != LSQOp::S {
+                    cpu.rob.buffer[i.rob_entry].issued = Some(cpu.cycle);
+                }
+                //Stores only leave the queue once committed so their entries may belong to someone else
+                if let Some(ref mut trace) = cpu.trace {
+                    match i.op {
+                        LSQOp::S => (),
+                        LSQOp::Fence => trace.stage(i.rob_entry, Stage::Writeback),
+                        _ => trace.stage(i.rob_entry, Stage::Issue),
+                    }
+                }
+            }
+        }
+    }
+    for thread in &mut cpu.threads {
+        thread.lsq.tick();
+    }
+}
+
+fn decode_thread(cpu: &mut CPU, thread: usize, decoded: &mut usize, renamed: &mut usize) {
+    let waiting = cpu.threads[thread].decode_unit.instruction_q.len();
+    cpu.threads[thread].decode_unit.dispatch_stall = None;
+    while *decoded < cpu.threads[thread].decode_unit.width {
+        let queued = cpu.threads[thread].decode_unit.instruction_q.len();
+        let rob_tail = cpu.rob.issue[thread];
+        let possible_instruction = cpu.threads[thread].decode_unit.get_next_instruction();
+        let renames = possible_instruction.is_some_and(|(_, _, instruction)| writes_register(instruction));
+        if renames && *renamed == cpu.threads[thread].decode_unit.rename_width {
+            cpu.threads[thread].decode_unit.rename_width_stalls += 1;
+            break;
+        }
         match possible_instruction {
-            Some((pc, instruction)) => {
-                let reset = cpu.decode_unit.reset;
+            Some((seq, pc, instruction)) => {
+                let reset = cpu.threads[thread].decode_unit.reset;
                 match reset {
                     true => {
-                        cpu.decode_unit.instruction_q.clear();
-                        cpu.decode_unit.reset = false;
+                        cpu.threads[thread].decode_unit.instruction_q.clear();
+                        cpu.threads[thread].decode_unit.reset = false;
                     },
                     false => {
                         match instruction {
                             EncodedInstruction::Noop            => {
-                                cpu.decode_unit.pop_instruction();
+                                cpu.threads[thread].decode_unit.pop_instruction();
                             }
                             EncodedInstruction::Halt            => {
                                 
                             },
                             EncodedInstruction::Addi(d, s, imm) => {
-                                cpu.issue_imm(d, s, imm, Op::Add);
+                                cpu.issue_imm(thread, d, s, imm, Op::Add);
                             },
                             EncodedInstruction::Add(d, s, t)    => {
-                                cpu.issue(d, s, t, Op::Add);
+                                cpu.issue(thread, d, s, t, Op::Add);
                             },
                             EncodedInstruction::And(d, s, t)    => {
-                                cpu.issue(d, s, t, Op::And);
+                                cpu.issue(thread, d, s, t, Op::And);
                             },
                             EncodedInstruction::Andi(d, s, imm) => {
-                                cpu.issue_imm(d, s, imm, Op::And);
+                                cpu.issue_imm(thread, d, s, imm, Op::And);
                             },
                             EncodedInstruction::Beq(s, t, inst) => {
-                                cpu.issue_branch2(s, t, inst, Op::Beq, pc);
-                                cpu.predict(instruction, pc);
+                                if let Some(rob_pos) = cpu.issue_branch2(thread, s, t, inst, Op::Beq, pc) {
+                                    cpu.predict(thread, instruction, pc, rob_pos);
+                                }
                             },
                             EncodedInstruction::Beqz(s, inst) => {
-                                cpu.predict(instruction, pc);
-                                cpu.issue_branch1(s, inst, Op::Beqz, pc);
+                                if let Some(rob_pos) = cpu.issue_branch1(thread, s, inst, Op::Beqz, pc) {
+                                    cpu.predict(thread, instruction, pc, rob_pos);
+                                }
                             }
                             EncodedInstruction::Blt(s, t, inst) => {
-                                cpu.predict(instruction, pc);
-                                cpu.issue_branch2(s, t, inst, Op::Blt, pc);
+                                if let Some(rob_pos) = cpu.issue_branch2(thread, s, t, inst, Op::Blt, pc) {
+                                    cpu.predict(thread, instruction, pc, rob_pos);
+                                }
                             },
                             EncodedInstruction::Bgt(s, t, inst) => {
-                                cpu.predict(instruction, pc);
-                                cpu.issue_branch2(s, t, inst, Op::Bgt, pc);
+                                if let Some(rob_pos) = cpu.issue_branch2(thread, s, t, inst, Op::Bgt, pc) {
+                                    cpu.predict(thread, instruction, pc, rob_pos);
+                                }
                             },
                             EncodedInstruction::Div(d, s, t)    => {
-                                cpu.issue(d, s, t, Op::Div);
+                                cpu.issue(thread, d, s, t, Op::Div);
+                            },
+                            EncodedInstruction::Fence           => {
+                                if !cpu.threads[thread].lsq.is_full() {
+                                    if let Some(rob_pos) = cpu.rob.commit_to(thread, 0) {
+                                        cpu.threads[thread].lsq.issue(LSQOp::Fence, pc, rob_pos, Operand::None, Operand::None);
+                                        cpu.threads[thread].decode_unit.pop_instruction();
+                                    }
+                                }
                             },
                             EncodedInstruction::J(inst)         => {
-                                cpu.issue_branch0(inst);
+                                cpu.issue_branch0(thread, inst);
                             },
                             EncodedInstruction::Ldc(d, imm)     => {
-                                cpu.issue1_imm(d, imm, Op::Mov);
+                                cpu.issue1_imm(thread, d, imm, Op::Mov);
+                            },
+                            EncodedInstruction::Ll(addr, dest)        => {
+                                if !cpu.threads[thread].lsq.is_full() && cpu.threads[thread].registers.can_rename() && !cpu.rob.is_full(thread) && cpu.reserve_read_ports(thread, &[addr]) {
+                                    if let Some(rob_pos) = cpu.rob.commit_to(thread, dest) {
+                                        let operand1 = cpu.get_operand(thread, addr);
+                                        cpu.rename_dest(thread, dest, rob_pos);
+                                        cpu.threads[thread].lsq.issue(LSQOp::LL, pc, rob_pos, operand1, Operand::None);
+                                        cpu.threads[thread].decode_unit.pop_instruction();
+                                    }
+                                }
                             },
                             EncodedInstruction::Lw(addr, dest)        => {
-                                if let Some(rob_pos) = cpu.rob.commit_to(dest) {
-                                    let operand1 = cpu.get_operand(addr);
-                                    cpu.registers.set_owner(dest, rob_pos);
-                                    cpu.lsq.issue(LSQOp::L, pc, rob_pos, operand1, Operand::None);
-                                    cpu.decode_unit.pop_instruction();
+                                if !cpu.threads[thread].lsq.is_full() && cpu.threads[thread].registers.can_rename() && !cpu.rob.is_full(thread) && cpu.reserve_read_ports(thread, &[addr]) {
+                                    if let Some(rob_pos) = cpu.rob.commit_to(thread, dest) {
+                                        let operand1 = cpu.get_operand(thread, addr);
+                                        cpu.rename_dest(thread, dest, rob_pos);
+                                        cpu.threads[thread].lsq.issue(LSQOp::L, pc, rob_pos, operand1, Operand::None);
+                                        cpu.threads[thread].decode_unit.pop_instruction();
+                                    }
                                 }
                             },
                             EncodedInstruction::Mod(d, s, t)    => {
-                                cpu.issue(d, s, t, Op::Mod);
+                                cpu.issue(thread, d, s, t, Op::Mod);
                             },
                             EncodedInstruction::Mov(d, s)       => {
-                                cpu.issue1(d, s, Op::Mov);
+                                cpu.issue1(thread, d, s, Op::Mov);
                             },
                             EncodedInstruction::Mult(d, s, t)   => {
-                                cpu.issue(d, s, t, Op::Mult);
+                                cpu.issue(thread, d, s, t, Op::Mult);
                             },
                             EncodedInstruction::Or(d, s, t)     => {
-                                cpu.issue(d, s, t, Op::Or);
+                                cpu.issue(thread, d, s, t, Op::Or);
                             },
                             EncodedInstruction::Sl(d, s, t)     => {
-                                cpu.issue_imm(d, s, t, Op::Sl);
+                                cpu.issue_imm(thread, d, s, t, Op::Sl);
                             },
                             EncodedInstruction::Sr(d, s, t)     => {
-                                cpu.issue_imm(d, s, t, Op::Sr);
+                                cpu.issue_imm(thread, d, s, t, Op::Sr);
                             },
                             EncodedInstruction::Sub(d, s, t)    => {
-                                cpu.issue(d, s, t, Op::Sub);
+                                cpu.issue(thread, d, s, t, Op::Sub);
                             },
                             EncodedInstruction::Subi(d, s, imm) => {
-                                cpu.issue_imm(d, s, imm, Op::Sub);
+                                cpu.issue_imm(thread, d, s, imm, Op::Sub);
+                            },
+                            EncodedInstruction::Sc(addr, val, dest)  => {
+                                if !cpu.threads[thread].lsq.is_full() && cpu.threads[thread].registers.can_rename() && !cpu.rob.is_full(thread) && cpu.reserve_read_ports(thread, &[addr, val]) {
+                                    if let Some(rob_pos) = cpu.rob.commit_to(thread, dest) {
+                                        let operand1 = cpu.get_operand(thread, addr);
+                                        let operand2 = cpu.get_operand(thread, val);
+                                        cpu.rename_dest(thread, dest, rob_pos);
+                                        cpu.threads[thread].lsq.issue(LSQOp::SC, pc, rob_pos, operand1, operand2);
+                                        cpu.threads[thread].decode_unit.pop_instruction();
+                                    }
+                                }
                             },
                             EncodedInstruction::Sw(addr, val)        => {
-                                if let Some(rob_pos) = cpu.rob.commit_to_store(val) {
-                                    let operand1 = cpu.get_operand(addr);
-                                    let operand2 = cpu.get_operand(val);
-                                    cpu.registers.set_owner(val, rob_pos);
-                                    cpu.lsq.issue(LSQOp::S, pc, rob_pos, operand1, operand2);
-                                    cpu.decode_unit.pop_instruction();
+                                if !cpu.threads[thread].lsq.is_full() && !cpu.rob.is_full(thread) && cpu.reserve_read_ports(thread, &[addr, val]) {
+                                    if let Some(rob_pos) = cpu.rob.commit_to_store(thread, val) {
+                                        let operand1 = cpu.get_operand(thread, addr);
+                                        let operand2 = cpu.get_operand(thread, val);
+                                        cpu.threads[thread].lsq.issue(LSQOp::S, pc, rob_pos, operand1, operand2);
+                                        cpu.threads[thread].decode_unit.pop_instruction();
+                                    }
                                 }
                             },
                             EncodedInstruction::Xor(d, s, t)    => {
-                                cpu.issue(d, s, t, Op::Xor);
+                                cpu.issue(thread, d, s, t, Op::Xor);
                             },
                         };
+                        //The youngest instruction still in flight, which a jump or no-op just decoded retires after
+                        let behind = if cpu.rob.thread_empty(thread) { None } else { Some(cpu.rob.dec(cpu.rob.issue[thread])) };
+                        if cpu.rob.issue[thread] != rob_tail {
+                            cpu.threads[thread].recovering = false;
+                            cpu.rob.buffer[rob_tail].pc = pc;
+                        } else if cpu.threads[thread].decode_unit.instruction_q.len() != queued {
+                            //With nothing older left to mispredict it is known to be down the right path
+                            match behind {
+                                Some(youngest) => cpu.threads[thread].jumps.push((youngest, pc)),
+                                None => cpu.threads[thread].profile[pc].executed += 1,
+                            }
+                        }
+                        if let Some(ref mut trace) = cpu.trace {
+                            if cpu.rob.issue[thread] != rob_tail {
+                                trace.dispatch(thread, seq, rob_tail);
+                            } else if cpu.threads[thread].decode_unit.instruction_q.len() != queued {
+                                trace.consume(thread, seq, behind);
+                            }
+                        }
                     },
                 };
             },
             None => (),
         };
-    }
-
-    //now dispatch
-    for fu in &mut cpu.exec_unit.func_units {
-        for rs in 0..cpu.exec_unit.rs_sts.len() {
-            //try to dipatch to functional unit
-            if let Some((x, y)) = cpu.exec_unit.rs_sts[rs].get_operands() {
-                if fu.dispatch(x, y, cpu.exec_unit.rs_sts[rs].operation, cpu.exec_unit.rs_sts[rs].rob_entry, cpu.exec_unit.rs_sts[rs].address) {
-                    //println!("Dispatching {} = {} {:?} {} from {}", cpu.exec_unit.rs_sts[rs].rob_entry, x, cpu.exec_unit.rs_sts[rs].operation, y, rs );
-                    cpu.exec_unit.rs_sts[rs].free();
-                    break; //found a functional unit to execute this RS 
-                }
+        //Nothing frees up resources during decode so a stalled instruction stays stalled this cycle
+        if cpu.threads[thread].decode_unit.instruction_q.len() == queued {
+            if let Some((_, _, instruction)) = possible_instruction {
+                cpu.threads[thread].decode_unit.dispatch_stall = cpu.dispatch_stall(thread, instruction);
             }
+            break;
         }
-    }
-
-    //Now check the LSQ if something can be executed
-    if cpu.exec_unit.mem_unit.finished() {
-        if let Some(i) = cpu.lsq.get_next_instruction() {
-            cpu.exec_unit.mem_unit.dispatch(i);
+        *decoded += 1;
+        if renames {
+            *renamed += 1;
         }
     }
+    let decode_unit = &mut cpu.threads[thread].decode_unit;
+    if waiting == 0 {
+        decode_unit.empty_cycles += 1;
+    } else if decode_unit.instruction_q.len() == waiting {
+        decode_unit.stall_cycles += 1;
+    }
 }
 
-fn execute(cpu: &mut CPU, memory: &mut [u32; MEM_SIZE]) {
+fn execute(cpu: &mut CPU, memories: &mut [[u32; MEM_SIZE]], mem_system: &mut MemorySystem) {
+    //Units are busy this cycle if they start it with work, as counted by their busy cycles
+    if let Some(ref mut timeline) = cpu.timeline {
+        for (i, fu) in cpu.exec_unit.func_units.iter().enumerate() {
+            timeline.activity(i, fu.activity());
+        }
+    }
 
     for fu in &mut cpu.exec_unit.func_units {
         fu.cycle();
     }
 
-    cpu.exec_unit.mem_unit.cycle(memory);
+    cpu.exec_unit.mem_unit.cycle(cpu.core, memories, mem_system);
+    if let Some(ref mut timeline) = cpu.timeline {
+        let track = cpu.exec_unit.func_units.len();
+        timeline.activity(track, cpu.exec_unit.mem_unit.activity());
+    }
+
+    if let Some(ref mut trace) = cpu.trace {
+        for fu in &cpu.exec_unit.func_units {
+            for &(rob_entry, _) in &fu.results {
+                trace.stage(rob_entry, Stage::Complete);
+            }
+        }
+        for &(rob_entry, _) in &cpu.exec_unit.mem_unit.results {
+            trace.stage(rob_entry, Stage::Complete);
+        }
+    }
 }
 
 fn writeback(cpu: &mut CPU) {
-    for fu in 0..cpu.exec_unit.func_units.len() {
-        if let Some((result, rob_entry)) = cpu.exec_unit.func_units[fu].get_result() {
-            
-                cpu.rob.insert(rob_entry, result);
-
-                //Resolve dependencies if there is any
-                //println!("CDB BROADCASTING: {:?} to ROB {}", result, rob_entry);
-                match result {
-                    ExecResult::Value(x) => {
-                        
-                        // resolve dependencies in the reservation stations
-                        for dependent in 0..cpu.exec_unit.rs_sts.len() {
-                            cpu.exec_unit.rs_sts[dependent].resolve_dependency(x, rob_entry);
-                        }
+    //Results that went through the register file instead of a bypass path arrive late
+    for (result, rob_entry) in cpu.exec_unit.bypass.due() {
+        deliver(cpu, result, rob_entry);
+    }
 
-                        //resolve dependencies in the load store queue
-                        cpu.lsq.resolve_dependency(x, rob_entry);
-                    },
-                    _ => (),
-                }
+    //Units with a finished result compete for the result buses, the memory unit last
+    let mut requests: Vec<(Option<usize>, usize, bool)> = Vec::new();
+    for fu in 0..cpu.exec_unit.func_units.len() {
+        if let Some((rob_entry, writes)) = cpu.exec_unit.func_units[fu].peek_result() {
+            requests.push((Some(fu), rob_entry, writes));
         }
     }
+    if let Some(rob_entry) = cpu.exec_unit.mem_unit.peek_result() {
+        requests.push((None, rob_entry, true));
+    }
+    let granted = cpu.exec_unit.cdb.arbitrate(requests, &cpu.rob, &mut cpu.exec_unit.ports);
 
-    let mem_res = cpu.exec_unit.mem_unit.get_result();
-    if let Some((rob_entry, ExecResult::Value(x))) = mem_res {
-        cpu.rob.insert(rob_entry, ExecResult::Value(x));
-        // resolve dependencies in the reservation stations
-        for dependent in 0..cpu.exec_unit.rs_sts.len() {
-            cpu.exec_unit.rs_sts[dependent].resolve_dependency(x, rob_entry);
+    for fu in granted {
+        let (result, rob_entry, source) = match fu {
+            Some(fu) => {
+                let (result, rob_entry) = cpu.exec_unit.func_units[fu].get_result().unwrap();
+                (result, rob_entry, Some(cpu.exec_unit.func_units[fu].fu_type))
+            },
+            None => {
+                let (rob_entry, result) = cpu.exec_unit.mem_unit.get_result().unwrap();
+                (result, rob_entry, None)
+            },
+        };
+        match result {
+            ExecResult::Value(_) if !cpu.exec_unit.bypass.forwards(source) => {
+                cpu.exec_unit.bypass.delay(result, rob_entry);
+            },
+            _ => deliver(cpu, result, rob_entry),
         }
+    }
 
-        //resolve dependencies in the load store queue
-        cpu.lsq.resolve_dependency(x, rob_entry);
+}
+
+fn deliver(cpu: &mut CPU, result: ExecResult, rob_entry: usize) {
+    cpu.rob.insert(rob_entry, result);
+    if let Some(ref mut trace) = cpu.trace {
+        trace.stage(rob_entry, Stage::Writeback);
     }
 
+    //Resolve dependencies if there is any
+    //println!("CDB BROADCASTING: {:?} to ROB {}", result, rob_entry);
+    match result {
+        ExecResult::Value(x) => {
+            cpu.broadcast(x, rob_entry);
+        },
+        _ => (),
+    }
 }
 
 fn commit(cpu: &mut CPU) {
-    for _ in 0..COMMIT_WIDTH {
-        match cpu.rob.get_commit() {
-            ReorderBufferResult::Writeback(res, rob, reg) => {
-                //println!("Writeback {} {}", res, reg);
-                cpu.registers.write_result(res, rob, reg);
-            },
-            ReorderBufferResult::BranchTaken(inst, pc) => {
-                //ROB also beign used to store predicted PC for branches
-                //If not equal then a misprediction occurred
-                let predicted_correct = cpu.branch_predictor.prediction_correct(inst, pc);
-                // IF not correctly predicted
-                //println!("Prediction correct: {} {}", predicted_correct, inst);
-                if !predicted_correct {
-                    //Need to clear RSs, FUs, Instruction Queue
-                    cpu.reset();
-                    //Also need to set the PC correctly
-                    cpu.fetch_unit.mispredict(inst);
-                    //need to let branch predictor know of incorrect prediction
-                    break;
+    let occupied = !cpu.rob.is_empty();
+    let entries: usize = (0..cpu.threads.len()).map(|t| cpu.rob.len(t)).sum();
+    cpu.rob.occupancy[entries] += 1;
+    let committed = cpu.rob.instructions_committed;
+    //Threads take turns at the commit bandwidth, a thread's turn ends when its head is not ready
+    let mut slots = cpu.rob.commit_width;
+    //Slots left over are put down to the first thread still running that could not use them
+    let mut stalled = None;
+    for thread in cpu.rotation() {
+        while slots > 0 {
+            slots -= 1;
+            let head = cpu.rob.commit[thread];
+            let result = cpu.rob.get_commit(thread);
+            let mut jumps = Vec::new();
+            if cpu.rob.commit[thread] != head {
+                jumps = cpu.threads[thread].jumps.iter().filter(|&&(entry, _)| entry == head).map(|&(_, pc)| pc).collect();
+                cpu.threads[thread].jumps.retain(|&(entry, _)| entry != head);
+                let entry = cpu.rob.buffer[head];
+                let profile = &mut cpu.threads[thread].profile[entry.pc];
+                profile.executed += 1;
+                if let Some(issued) = entry.issued {
+                    profile.latency += cpu.cycle - issued;
+                    profile.timed += 1;
                 }
-            },
-            ReorderBufferResult::BranchNotTaken(pc) => {
-                //ROB also beign used to store predicted PC for branches
-                //If not equal then a misprediction occurred
-                let taken_pc = pc + 1;
-                let predicted_correct = cpu.branch_predictor.prediction_correct(taken_pc, pc);
-                // IF not correctly predicted
-                //println!("Prediction correct: {} {}", predicted_correct, taken_pc);
-                if !predicted_correct {
-                    //Need to clear RSs, FUs, Instruction Queue
-                    cpu.reset();
-                    //Also need to set the PC correctly
-                    cpu.fetch_unit.mispredict(taken_pc);
-                    //need to let branch predictor know of incorrect prediction
-                    break;
+                if let Some(ref mut trace) = cpu.trace {
+                    trace.retire(head);
                 }
-            },
-            ReorderBufferResult::Store(r) => {
-                cpu.lsq.committed(r);
             }
-            ReorderBufferResult::None => (),
-        };
+            match result {
+                ReorderBufferResult::Writeback(res, rob, reg) => {
+                    //println!("Writeback {} {}", res, reg);
+                    let rename = cpu.rob.buffer[rob].rename;
+                    cpu.threads[thread].registers.retire(res, rob, reg, rename);
+                },
+                ReorderBufferResult::BranchTaken(inst, pc, rob) => {
+                    //ROB also beign used to store predicted PC for branches
+                    //If not equal then a misprediction occurred
+                    let predicted = cpu.rob.buffer[rob].predicted;
+                    let predicted_correct = cpu.branch_predictor.prediction_correct(predicted, inst, pc);
+                    let checkpoint = cpu.rob.buffer[rob].checkpoint.expect("Branch committed without a checkpoint");
+                    // IF not correctly predicted
+                    //println!("Prediction correct: {} {}", predicted_correct, inst);
+                    if predicted_correct {
+                        cpu.threads[thread].registers.release_checkpoint(checkpoint);
+                    } else {
+                        //Need to clear RSs, FUs, Instruction Queue
+                        cpu.recover(thread, checkpoint);
+                        //Also need to set the PC correctly
+                        cpu.threads[thread].fetch_unit.mispredict(inst);
+                        cpu.threads[thread].profile[pc].mispredicted += 1;
+                        //need to let branch predictor know of incorrect prediction
+                        break;
+                    }
+                },
+                ReorderBufferResult::BranchNotTaken(pc, rob) => {
+                    //ROB also beign used to store predicted PC for branches
+                    //If not equal then a misprediction occurred
+                    let taken_pc = pc + 1;
+                    let predicted = cpu.rob.buffer[rob].predicted;
+                    let predicted_correct = cpu.branch_predictor.prediction_correct(predicted, taken_pc, pc);
+                    let checkpoint = cpu.rob.buffer[rob].checkpoint.expect("Branch committed without a checkpoint");
+                    // IF not correctly predicted
+                    //println!("Prediction correct: {} {}", predicted_correct, taken_pc);
+                    if predicted_correct {
+                        cpu.threads[thread].registers.release_checkpoint(checkpoint);
+                    } else {
+                        //Need to clear RSs, FUs, Instruction Queue
+                        cpu.recover(thread, checkpoint);
+                        //Also need to set the PC correctly
+                        cpu.threads[thread].fetch_unit.mispredict(taken_pc);
+                        cpu.threads[thread].profile[pc].mispredicted += 1;
+                        //need to let branch predictor know of incorrect prediction
+                        break;
+                    }
+                },
+                ReorderBufferResult::Store(r) => {
+                    cpu.threads[thread].lsq.committed(r);
+                }
+                ReorderBufferResult::None => {
+                    slots += 1;
+                    break;
+                },
+            };
+            //A mispredicted branch breaks out above so the jumps decoded behind it are dropped
+            for pc in jumps {
+                cpu.threads[thread].profile[pc].executed += 1;
+            }
+            if let Some(ref mut trace) = cpu.trace {
+                trace.retire_consumed(thread, head);
+            }
+        }
+        if slots > 0 && stalled.is_none() && !cpu.thread_finished(thread) {
+            stalled = Some(thread);
+        }
+    }
+    cpu.top_down.retiring += (cpu.rob.commit_width - slots) as u64;
+    if slots > 0 {
+        let loss = stalled.map_or(SlotLoss::FrontEnd, |thread| cpu.slot_loss(thread));
+        cpu.top_down.add(loss, slots as u64);
+    }
+    if !occupied {
+        cpu.rob.empty_cycles += 1;
+    } else if cpu.rob.instructions_committed == committed {
+        cpu.rob.stall_cycles += 1;
     }
 }
 
-struct CPU {
+#[derive(Copy, Clone)]
+struct PipelineConfig {
+    fetch: usize,
+    decode: usize,
+    rename: usize,
+    commit: usize,
+    // Capacity of the queue between fetch and decode
+    queue: usize,
+    // Capacity of each load store queue, unlimited if not given
+    lsq: Option<usize>,
+    // Number of stages each step of the front end takes
+    fetch_stages: u32,
+    decode_stages: u32,
+    rename_stages: u32,
+    dispatch_stages: u32,
+}
+
+impl PipelineConfig {
+    // Cycles an instruction spends in front end latches on top of the single stage model
+    fn front_end_delay(&self) -> u32 {
+        if self.fetch_stages == 0 || self.decode_stages == 0 || self.rename_stages == 0 {
+            panic!("Every front end step needs at least one stage");
+        }
+        self.fetch_stages + self.decode_stages + self.rename_stages - 3
+    }
+
+    // Cycles between an instruction being renamed and it being able to execute
+    fn dispatch_delay(&self) -> u32 {
+        if self.dispatch_stages == 0 {
+            panic!("Dispatch needs at least one stage");
+        }
+        self.dispatch_stages - 1
+    }
+}
+
+// Everything an out of order core is built from besides its programs and execution unit
+#[derive(Copy, Clone)]
+struct CoreConfig {
+    pred_type: usize,
+    pipeline: PipelineConfig,
+    scheme: RenameScheme,
+    // Physical registers when renaming into a register file
+    prf_size: usize,
+    checkpoints: usize,
+    sharing: RobSharing,
+    fetch_policy: FetchPolicy,
+    consistency: Consistency,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum FetchPolicy {
+    RoundRobin,
+    // Favours the thread with the fewest instructions waiting in the front end and reservation stations
+    ICount,
+}
+
+impl FetchPolicy {
+    fn parse(name: &str) -> FetchPolicy {
+        match name.to_lowercase().as_str() {
+            "round-robin" | "rr" => FetchPolicy::RoundRobin,
+            "icount" => FetchPolicy::ICount,
+            _ => panic!("Unaccepted fetch policy {}", name),
+        }
+    }
+}
+
+// Why commit slots went unused, bad speculation being the refill after a misprediction and the
+// front end an empty ROB otherwise, the rest being back end causes
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum SlotLoss {
+    BadSpeculation,
+    FrontEnd,
+    RobFull,
+    RsFull,
+    LsqFull,
+    Memory,
+    Divider,
+    // Waiting on other execution with nothing full
+    Core,
+}
+
+// Every commit slot of every cycle either retires an instruction or is lost, as in the top-down method
+#[derive(Debug, Default, Copy, Clone)]
+struct TopDown {
+    retiring: u64,
+    bad_speculation: u64,
+    front_end: u64,
+    rob_full: u64,
+    rs_full: u64,
+    lsq_full: u64,
+    memory: u64,
+    divider: u64,
+    core: u64,
+}
+
+impl TopDown {
+    fn add(&mut self, loss: SlotLoss, slots: u64) {
+        match loss {
+            SlotLoss::BadSpeculation => self.bad_speculation += slots,
+            SlotLoss::FrontEnd => self.front_end += slots,
+            SlotLoss::RobFull => self.rob_full += slots,
+            SlotLoss::RsFull => self.rs_full += slots,
+            SlotLoss::LsqFull => self.lsq_full += slots,
+            SlotLoss::Memory => self.memory += slots,
+            SlotLoss::Divider => self.divider += slots,
+            SlotLoss::Core => self.core += slots,
+        }
+    }
+
+    fn merge(&mut self, other: &TopDown) {
+        self.retiring += other.retiring;
+        self.bad_speculation += other.bad_speculation;
+        self.front_end += other.front_end;
+        self.rob_full += other.rob_full;
+        self.rs_full += other.rs_full;
+        self.lsq_full += other.lsq_full;
+        self.memory += other.memory;
+        self.divider += other.divider;
+        self.core += other.core;
+    }
+
+    fn back_end(&self) -> u64 {
+        self.rob_full + self.rs_full + self.lsq_full + self.memory + self.divider + self.core
+    }
+
+    fn slots(&self) -> u64 {
+        self.retiring + self.bad_speculation + self.front_end + self.back_end()
+    }
+
+    fn record(&self, stats: &mut Stats) {
+        stats.counter("retiring", self.retiring);
+        stats.counter("bad_speculation", self.bad_speculation);
+        stats.counter("front_end", self.front_end);
+        stats.counter("rob_full", self.rob_full);
+        stats.counter("rs_full", self.rs_full);
+        stats.counter("lsq_full", self.lsq_full);
+        stats.counter("memory", self.memory);
+        stats.counter("divider", self.divider);
+        stats.counter("core", self.core);
+    }
+}
+
+// What one static instruction did over a run, counting only the instructions that committed
+#[derive(Debug, Default, Copy, Clone)]
+struct PcProfile {
+    executed: u64,
+    mispredicted: u64,
+    // Cycles from issue to commit summed over the executions it was measured for
+    latency: u64,
+    timed: u64,
+    l1d_misses: u64,
+}
+
+impl PcProfile {
+    fn merge(&mut self, other: &PcProfile) {
+        self.executed += other.executed;
+        self.mispredicted += other.mispredicted;
+        self.latency += other.latency;
+        self.timed += other.timed;
+        self.l1d_misses += other.l1d_misses;
+    }
+}
+
+// The state each hardware thread keeps to itself, everything else in the CPU is shared
+#[derive(Debug)]
+struct Thread {
     fetch_unit: FetchUnit,
     decode_unit: DecodeUnit,
-    exec_unit: ExecUnit,
     registers: Registers,
+    lsq: LSQ,
+    // Where the thread's instructions sit in the cache address space
+    code_base: usize,
+    // Cycle by which the thread had committed its last instruction
+    finished_at: Option<u64>,
+    // Set by a misprediction until the first instruction down the right path is renamed
+    recovering: bool,
+    // Indexed by pc
+    profile: Vec<PcProfile>,
+    // Jumps and no-ops never take a ROB entry so are counted once the entry decoded before them commits,
+    // held here as that entry and their pc
+    jumps: Vec<(usize, usize)>,
+}
+
+impl Thread {
+    fn finished(&self) -> bool {
+        self.fetch_unit.finished() &&
+        self.decode_unit.finished() &&
+        self.lsq.finished()
+    }
+}
+
+struct CPU {
+    threads: Vec<Thread>,
+    exec_unit: ExecUnit,
     rob: ReorderBuffer,
     branch_predictor: BranchPredictor,
-    lsq: LSQ,
+    fetch_policy: FetchPolicy,
+    // Thread first in line for fetch, decode and commit bandwidth, rotated every cycle
+    priority: usize,
+    // Which of the memory system's private caches this core uses
+    core: usize,
+    trace: Option<PipelineTrace>,
+    timeline: Option<Timeline>,
+    top_down: TopDown,
+    cycle: u64,
 }
 
 impl fmt::Debug for CPU {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Fetch unit: {:?}\n\nDecode Unit: {:?}\n\nExec Unit: {:?}\n\nRegisters: {:?}\n\nROB: {:?}\n\nLSQ: {:?}", self.fetch_unit, self.decode_unit, self.exec_unit, self.registers, self.rob, self.lsq)
+        for (t, thread) in self.threads.iter().enumerate() {
+            if self.threads.len() > 1 {
+                write!(f, "Thread {}\n\n", t)?;
+            }
+            write!(f, "Fetch unit: {:?}\n\nDecode Unit: {:?}\n\nRegisters: {:?}\n\nLSQ: {:?}\n\n", thread.fetch_unit, thread.decode_unit, thread.registers, thread.lsq)?;
+        }
+        write!(f, "Exec Unit: {:?}\n\nROB: {:?}", self.exec_unit, self.rob)
     }
 }
 
 impl CPU {
-    fn new(instructions: Vec<EncodedInstruction>, pred_type: usize, fetch_width: usize, num_rs: usize) -> CPU {
+    fn new(programs: Vec<Vec<EncodedInstruction>>, mut exec_unit: ExecUnit, config: CoreConfig) -> CPU {
+        let pipeline = config.pipeline;
+        exec_unit.dispatch_delay = pipeline.dispatch_delay();
+        exec_unit.mem_unit.model = config.consistency;
+        let threads = programs.len();
         CPU {
-            fetch_unit: FetchUnit::new(instructions, fetch_width),
-            decode_unit: DecodeUnit::new(),
-            exec_unit: ExecUnit::new(num_rs),
-            registers: Registers::new(),
-            rob: ReorderBuffer::new(),
-            branch_predictor: BranchPredictor::new(pred_type),
-            lsq: LSQ::new(),
-        }
-    }
-
-    fn issue(&mut self, d: usize, s: usize, t: usize, op: Op) {
-        if let Some(r) = self.exec_unit.get_free_rs() {
-            if let Some(rob_pos) = self.rob.commit_to(d) {
-                let operand1 = self.get_operand(s);
-                let operand2 = self.get_operand(t);
-                self.registers.set_owner(d, rob_pos);
+            threads: programs.into_iter().enumerate().map(|(t, instructions)| Thread {
+                profile: vec![PcProfile::default(); instructions.len()],
+                jumps: Vec::new(),
+                fetch_unit: FetchUnit::new(instructions, pipeline.fetch),
+                decode_unit: DecodeUnit::new(&pipeline),
+                registers: Registers::new(config.scheme, config.prf_size, config.checkpoints),
+                lsq: LSQ::new(pipeline.dispatch_delay(), pipeline.lsq, config.consistency),
+                code_base: t * THREAD_ADDRESS_SPACE,
+                finished_at: None,
+                recovering: false,
+            }).collect(),
+            exec_unit,
+            rob: ReorderBuffer::new(pipeline.commit, threads, config.sharing),
+            branch_predictor: BranchPredictor::new(config.pred_type),
+            fetch_policy: config.fetch_policy,
+            priority: 0,
+            core: 0,
+            trace: None,
+            timeline: None,
+            top_down: TopDown::default(),
+            cycle: 0,
+        }
+    }
+
+    fn record(&self, stats: &mut Stats) {
+        stats.counter("instructions", self.rob.instructions_committed as u64);
+        stats.enter("rob");
+        self.rob.record(stats);
+        stats.leave();
+        stats.enter("branch_predictor");
+        self.branch_predictor.record(stats);
+        stats.leave();
+        self.exec_unit.record(stats);
+        stats.enter("top_down");
+        self.top_down.record(stats);
+        stats.leave();
+        for (t, thread) in self.threads.iter().enumerate() {
+            stats.enter(&format!("thread{}", t));
+            stats.counter("instructions", self.rob.thread_committed[t] as u64);
+            if let Some(cycles) = thread.finished_at {
+                stats.counter("finished_at", cycles);
+            }
+            stats.enter("fetch");
+            thread.fetch_unit.record(stats);
+            stats.leave();
+            stats.enter("decode");
+            thread.decode_unit.record(stats);
+            stats.leave();
+            stats.enter("rename");
+            thread.registers.record(stats);
+            stats.leave();
+            stats.enter("lsq");
+            thread.lsq.record(stats);
+            stats.leave();
+            stats.values("registers", &thread.registers.gprs);
+            stats.leave();
+        }
+    }
+
+    // A thread's counts by pc, with the L1 data cache misses the memory unit saw
+    fn profile(&self, thread: usize) -> Vec<PcProfile> {
+        let mut profile = self.threads[thread].profile.clone();
+        for (&(t, pc), &misses) in &self.exec_unit.mem_unit.misses {
+            if t == thread {
+                profile[pc].l1d_misses = misses;
+            }
+        }
+        profile
+    }
+
+    fn issue(&mut self, thread: usize, d: usize, s: usize, t: usize, op: Op) {
+        if let Some(r) = self.exec_unit.get_free_rs(op) {
+            if !self.threads[thread].registers.can_rename() {
+                return;
+            }
+            if self.rob.is_full(thread) || !self.reserve_read_ports(thread, &[s, t]) {
+                return;
+            }
+            if let Some(rob_pos) = self.rob.commit_to(thread, d) {
+                let operand1 = self.get_operand(thread, s);
+                let operand2 = self.get_operand(thread, t);
+                self.rename_dest(thread, d, rob_pos);
                 self.exec_unit.issue(operand1, operand2, op, r, rob_pos);
-                self.decode_unit.pop_instruction();
+                self.threads[thread].decode_unit.pop_instruction();
             }
         }
     }
 
-    fn issue1(&mut self, d: usize, s: usize, op: Op) {
-        if let Some(r) = self.exec_unit.get_free_rs() {
-            if let Some(rob_pos) = self.rob.commit_to(d) {
-                let operand1 = self.get_operand(s);
-                self.registers.set_owner(d, rob_pos);
+    fn issue1(&mut self, thread: usize, d: usize, s: usize, op: Op) {
+        if let Some(r) = self.exec_unit.get_free_rs(op) {
+            if !self.threads[thread].registers.can_rename() {
+                return;
+            }
+            if self.rob.is_full(thread) || !self.reserve_read_ports(thread, &[s]) {
+                return;
+            }
+            if let Some(rob_pos) = self.rob.commit_to(thread, d) {
+                let operand1 = self.get_operand(thread, s);
+                self.rename_dest(thread, d, rob_pos);
                 self.exec_unit.issue(operand1, Operand::None, op, r, rob_pos);
-                self.decode_unit.pop_instruction();
+                self.threads[thread].decode_unit.pop_instruction();
             }
         }
     }
 
-    fn issue1_imm(&mut self, d: usize, imm: u32, op: Op) {
-        if let Some(r) = self.exec_unit.get_free_rs() {
-            if let Some(rob_pos) = self.rob.commit_to(d) {
-                self.registers.set_owner(d, rob_pos);
+    fn issue1_imm(&mut self, thread: usize, d: usize, imm: u32, op: Op) {
+        if let Some(r) = self.exec_unit.get_free_rs(op) {
+            if !self.threads[thread].registers.can_rename() {
+                return;
+            }
+            if let Some(rob_pos) = self.rob.commit_to(thread, d) {
+                self.rename_dest(thread, d, rob_pos);
                 self.exec_unit.issue(Operand::Value(imm), Operand::None, op, r, rob_pos);
-                self.decode_unit.pop_instruction();
+                self.threads[thread].decode_unit.pop_instruction();
             }
         }
     }
 
-    fn issue_imm(&mut self, d: usize, s: usize, imm: u32, op: Op) {
-        if let Some(r) = self.exec_unit.get_free_rs() {
-            if let Some(rob_pos) = self.rob.commit_to(d) {
-                let operand1 = self.get_operand(s);
-                self.registers.set_owner(d, rob_pos);
+    fn issue_imm(&mut self, thread: usize, d: usize, s: usize, imm: u32, op: Op) {
+        if let Some(r) = self.exec_unit.get_free_rs(op) {
+            if !self.threads[thread].registers.can_rename() {
+                return;
+            }
+            if self.rob.is_full(thread) || !self.reserve_read_ports(thread, &[s]) {
+                return;
+            }
+            if let Some(rob_pos) = self.rob.commit_to(thread, d) {
+                let operand1 = self.get_operand(thread, s);
+                self.rename_dest(thread, d, rob_pos);
                 self.exec_unit.issue(operand1, Operand::Value(imm), op, r, rob_pos);
-                self.decode_unit.pop_instruction();
+                self.threads[thread].decode_unit.pop_instruction();
             }
         }
     }
 
-    fn issue_branch0(&mut self, inst: usize) {
-        self.fetch_unit.speculate(inst);
-        self.decode_unit.clear_instructions();
-        self.decode_unit.pop_instruction();
+    fn issue_branch0(&mut self, thread: usize, inst: usize) {
+        let thread = &mut self.threads[thread];
+        thread.fetch_unit.speculate(inst);
+        thread.decode_unit.clear_instructions();
+        thread.decode_unit.pop_instruction();
     }
 
-    fn issue_branch1(&mut self, s: usize, inst: usize, op: Op, pc: usize) {
-        if let Some(r) = self.exec_unit.get_free_rs() {
-            if let Some(rob_pos) = self.rob.commit_to(pc) {
-                let operand1 = self.get_operand(s);
+    fn issue_branch1(&mut self, thread: usize, s: usize, inst: usize, op: Op, pc: usize) -> Option<usize> {
+        if let Some(r) = self.exec_unit.get_free_rs(op) {
+            if !self.threads[thread].registers.can_checkpoint() {
+                return None;
+            }
+            if self.rob.is_full(thread) || !self.reserve_read_ports(thread, &[s]) {
+                return None;
+            }
+            if let Some(rob_pos) = self.rob.commit_to(thread, pc) {
+                self.rob.buffer[rob_pos].checkpoint = Some(self.threads[thread].registers.take_checkpoint());
+                let operand1 = self.get_operand(thread, s);
                 self.exec_unit.issue_branch(operand1, Operand::None, op, r, rob_pos, inst);
-                self.decode_unit.pop_instruction();
-             }
+                self.threads[thread].decode_unit.pop_instruction();
+                return Some(rob_pos);
+            }
         }
+        None
     }
 
-    fn issue_branch2(&mut self, s: usize, t: usize, inst: usize, op: Op, pc: usize) {
-        if let Some(r) = self.exec_unit.get_free_rs() {
-            if let Some(rob_pos) = self.rob.commit_to(pc) {
-                let operand1 = self.get_operand(s);
-                let operand2 = self.get_operand(t);
+    fn issue_branch2(&mut self, thread: usize, s: usize, t: usize, inst: usize, op: Op, pc: usize) -> Option<usize> {
+        if let Some(r) = self.exec_unit.get_free_rs(op) {
+            if !self.threads[thread].registers.can_checkpoint() {
+                return None;
+            }
+            if self.rob.is_full(thread) || !self.reserve_read_ports(thread, &[s, t]) {
+                return None;
+            }
+            if let Some(rob_pos) = self.rob.commit_to(thread, pc) {
+                self.rob.buffer[rob_pos].checkpoint = Some(self.threads[thread].registers.take_checkpoint());
+                let operand1 = self.get_operand(thread, s);
+                let operand2 = self.get_operand(thread, t);
                 self.exec_unit.issue_branch(operand1, operand2, op, r, rob_pos, inst);
-                self.decode_unit.pop_instruction();
+                self.threads[thread].decode_unit.pop_instruction();
+                return Some(rob_pos);
             }
         }
+        None
     }
 
-    fn predict(&mut self, inst: EncodedInstruction, pc: usize) {
+    //Only predicts once the branch has issued, redirecting fetch would otherwise throw away a stalled branch
+    fn predict(&mut self, thread: usize, inst: EncodedInstruction, pc: usize, rob_pos: usize) {
         let predicted = self.branch_predictor.predict(inst, pc);
+        self.rob.buffer[rob_pos].predicted = predicted;
         if predicted != pc + 1 {
-            self.fetch_unit.speculate(predicted);
-            self.decode_unit.clear_instructions();
+            self.threads[thread].fetch_unit.speculate(predicted);
+            self.threads[thread].decode_unit.clear_instructions();
         }
     }
 
     fn finished(&self) -> bool {
-        self.fetch_unit.finished() &&
-        self.decode_unit.finished() &&
+        self.threads.iter().all(|t| t.finished()) &&
         self.exec_unit.finished() &&
-        self.rob.is_empty() &&
-        self.lsq.finished()
+        self.rob.is_empty()
+    }
+
+    fn thread_finished(&self, thread: usize) -> bool {
+        self.threads[thread].finished() && self.rob.thread_empty(thread)
+    }
+
+    // Instructions a thread has fetched that are yet to be issued to a functional unit or memory
+    fn icount(&self, thread: usize) -> usize {
+        let t = &self.threads[thread];
+        t.decode_unit.instruction_q.len() + t.decode_unit.latches.len() + t.lsq.lsq.len() + self.exec_unit.occupancy(thread)
+    }
+
+    // Threads in the order they get a turn this cycle
+    fn rotation(&self) -> Vec<usize> {
+        let threads = self.threads.len();
+        (0..threads).map(|i| (self.priority + i) % threads).collect()
     }
 
-    fn get_operand(&self, reg: usize) -> Operand {
-        let o = self.read_reg(reg);
+    fn select_fetch(&self, ready: &[usize]) -> Option<usize> {
+        let order: Vec<usize> = self.rotation().into_iter().filter(|t| ready.contains(t)).collect();
+        match self.fetch_policy {
+            FetchPolicy::RoundRobin => order.first().cloned(),
+            FetchPolicy::ICount => order.iter().cloned().min_by_key(|&t| self.icount(t)),
+        }
+    }
+
+    //Source operands already available are read from the register file or ROB and need a read port,
+    //reserved last so an instruction stalled on anything else leaves the ports to the others
+    fn reserve_read_ports(&mut self, thread: usize, regs: &[usize]) -> bool {
+        let reads = regs.iter().filter(|&&reg| {
+            matches!(self.get_operand(thread, reg), Operand::Value(_))
+        }).count();
+        self.exec_unit.ports.reserve_reads(reads)
+    }
+
+    fn get_operand(&self, thread: usize, reg: usize) -> Operand {
+        let o = self.read_reg(thread, reg);
         match o {
-            Operand::Rob(r) => {
+            Operand::Tag(r) if self.threads[thread].registers.scheme == RenameScheme::Rob => {
                 if let Some(result) = self.rob.buffer[r].result {
                     if let ExecResult::Value(x) = result {
                         Operand::Value(x)
@@ -531,126 +2274,593 @@ impl CPU {
         }
     }
 
-    fn read_reg(&self, reg: usize) -> Operand {
-        match self.registers.rat[reg] {
+    //Physical register tags are offset by thread as every thread has its own physical register file
+    fn read_reg(&self, thread: usize, reg: usize) -> Operand {
+        let registers = &self.threads[thread].registers;
+        if registers.scheme == RenameScheme::Prf {
+            let preg = registers.map[reg];
+            return if registers.prf_ready[preg] {
+                Operand::Value(registers.prf[preg])
+            } else {
+                Operand::Tag(thread * registers.prf.len() + preg)
+            };
+        }
+        match registers.rat[reg] {
             None => {
-                Operand::Value(self.registers.gprs[reg])
+                Operand::Value(registers.gprs[reg])
             },
             Some(rob_entry) => {
                 if let Some(ExecResult::Value(x)) = self.rob.buffer[rob_entry].result {
                     Operand::Value(x)
                 } else {
-                    Operand::Rob(rob_entry)
+                    Operand::Tag(rob_entry)
+                }
+            },
+        }
+    }
+
+    fn rename_dest(&mut self, thread: usize, reg: usize, rob_entry: usize) {
+        self.rob.buffer[rob_entry].rename = self.threads[thread].registers.rename(reg, rob_entry);
+    }
+
+    //Wakes up everything waiting on the result of a ROB entry
+    fn broadcast(&mut self, x: u32, rob_entry: usize) {
+        let thread = ReorderBuffer::thread_of(rob_entry);
+        let registers = &mut self.threads[thread].registers;
+        let tag = match registers.scheme {
+            RenameScheme::Rob => rob_entry,
+            RenameScheme::Prf => {
+                let (preg, _) = self.rob.buffer[rob_entry].rename.expect("Value produced without a destination register");
+                registers.write_physical(preg, x);
+                thread * registers.prf.len() + preg
+            },
+        };
+
+        // resolve dependencies in the reservation stations
+        for dependent in 0..self.exec_unit.rs_sts.len() {
+            self.exec_unit.rs_sts[dependent].resolve_dependency(x, tag);
+        }
+
+        //resolve dependencies in the load store queue
+        self.threads[thread].lsq.resolve_dependency(x, tag);
+    }
+
+    // The full structure stopping an instruction being dispatched, checking the ROB before the queue it goes to
+    fn dispatch_stall(&self, thread: usize, instruction: EncodedInstruction) -> Option<SlotLoss> {
+        let memory = match instruction {
+            EncodedInstruction::Noop | EncodedInstruction::Halt | EncodedInstruction::J(_) => return None,
+            EncodedInstruction::Lw(_, _) | EncodedInstruction::Sw(_, _) | EncodedInstruction::Ll(_, _) |
+            EncodedInstruction::Sc(_, _, _) | EncodedInstruction::Fence => true,
+            _ => false,
+        };
+        if self.rob.is_full(thread) {
+            Some(SlotLoss::RobFull)
+        } else if memory && self.threads[thread].lsq.is_full() {
+            Some(SlotLoss::LsqFull)
+        } else if !memory && self.exec_unit.scheduler_full(DecodedInstruction::new(instruction).unwrap().op) {
+            Some(SlotLoss::RsFull)
+        } else {
+            None
+        }
+    }
+
+    // Why a thread could not commit, blaming what its oldest instruction waits on before what stops dispatch
+    fn slot_loss(&self, thread: usize) -> SlotLoss {
+        if self.rob.thread_empty(thread) {
+            return if self.threads[thread].recovering { SlotLoss::BadSpeculation } else { SlotLoss::FrontEnd };
+        }
+        let head = self.rob.commit[thread];
+        if self.threads[thread].lsq.holds(head) || self.exec_unit.mem_unit.holds(head) {
+            SlotLoss::Memory
+        } else if self.exec_unit.divides(head) {
+            SlotLoss::Divider
+        } else {
+            self.threads[thread].decode_unit.dispatch_stall.unwrap_or(SlotLoss::Core)
+        }
+    }
+
+    //Restores the rename map from the mispredicted branch's checkpoint and squashes everything after it
+    fn recover(&mut self, thread: usize, checkpoint: usize) {
+        let mut entry = self.rob.commit[thread];
+        while entry != self.rob.issue[thread] {
+            if let Some(ref mut trace) = self.trace {
+                trace.squash(entry);
+            }
+            let registers = &mut self.threads[thread].registers;
+            if let Some((preg, _)) = self.rob.buffer[entry].rename {
+                registers.free_list.push_back(preg);
+            }
+            if let Some(younger) = self.rob.buffer[entry].checkpoint {
+                registers.release_checkpoint(younger);
+            }
+            entry = self.rob.inc(entry);
+        }
+        self.threads[thread].registers.restore_checkpoint(checkpoint);
+        self.threads[thread].recovering = true;
+        self.threads[thread].jumps.clear();
+        if let Some(ref mut trace) = self.trace {
+            trace.squash_consumed(thread);
+        }
+        self.exec_unit.reset(thread);
+        self.threads[thread].decode_unit.reset();
+        self.rob.empty(thread);
+        self.threads[thread].lsq.clear();
+    }
+}
+
+#[derive(Debug)]
+struct FetchUnit {
+    width: usize,
+    pc: usize,
+    exec_pc: Option<usize>,
+    instructions: Vec<EncodedInstruction>,
+    stalled: bool,
+    reset: bool,
+    stall_cycles: u32,
+    filled_line: Option<usize>,
+    fetch_blocks: u64,
+    instructions_fetched: u64,
+    icache_stall_cycles: u64,
+    queue_full_cycles: u64,
+    redirects: u64,
+}
+
+impl FetchUnit {
+    fn new(encoded_instructions: Vec<EncodedInstruction>, width: usize) -> FetchUnit {
+        FetchUnit {
+            width: width,
+            pc: 0,
+            exec_pc: None,
+            instructions: encoded_instructions,
+            stalled: false,
+            reset: false,
+            stall_cycles: 0,
+            filled_line: None,
+            fetch_blocks: 0,
+            instructions_fetched: 0,
+            icache_stall_cycles: 0,
+            queue_full_cycles: 0,
+            redirects: 0,
+        }
+    }
+
+    fn record(&self, stats: &mut Stats) {
+        stats.counter("blocks", self.fetch_blocks);
+        stats.counter("instructions", self.instructions_fetched);
+        stats.counter("icache_stall_cycles", self.icache_stall_cycles);
+        stats.counter("queue_full_cycles", self.queue_full_cycles);
+        stats.counter("redirects", self.redirects);
+    }
+
+    fn finished(&self) -> bool {
+        if self.pc < self.instructions.len() {
+            false
+        } else {
+            true
+        }
+    }
+
+    fn speculate(&mut self, pc: usize) {
+        self.redirect(pc);
+    }
+
+    fn mispredict(&mut self, new_pc: usize) {
+        self.redirect(new_pc);
+    }
+
+    //An outstanding instruction cache miss is abandoned when fetch is redirected
+    fn redirect(&mut self, pc: usize) {
+        self.redirects += 1;
+        self.reset = true;
+        self.pc = pc;
+        self.stall_cycles = 0;
+        self.filled_line = None;
+    }
+
+    fn get_instruction(&self) -> EncodedInstruction {
+        if self.pc < self.instructions.len() {
+            self.instructions[self.pc]
+        } else {
+            EncodedInstruction::Halt
+        }
+    }
+}
+
+#[derive(Debug)]
+struct DecodeUnit {
+    // Instructions by fetch sequence number and pc
+    instruction_q: LinkedList<(u64, usize, EncodedInstruction)>,
+    stalled: bool,
+    reset: bool,
+    width: usize,
+    rename_width: usize,
+    capacity: usize,
+    // Instructions still in the front end stages before the queue, with the cycles left
+    latches: LinkedList<(u32, u64, usize, EncodedInstruction)>,
+    depth: u32,
+    latch_capacity: usize,
+    // The full structure that last stopped the head of the queue dispatching
+    dispatch_stall: Option<SlotLoss>,
+    // Cycles with instructions waiting where none left the queue
+    stall_cycles: u64,
+    empty_cycles: u64,
+    rename_width_stalls: u64,
+}
+
+impl DecodeUnit {
+    fn new(pipeline: &PipelineConfig) -> DecodeUnit {
+        if pipeline.decode == 0 || pipeline.rename == 0 || pipeline.queue == 0 {
+            panic!("Decode width, rename width and instruction queue size must be non zero");
+        }
+        let depth = pipeline.front_end_delay();
+        DecodeUnit {
+            instruction_q: LinkedList::new(),
+            stalled: false,
+            reset: false,
+            width: pipeline.decode,
+            rename_width: pipeline.rename,
+            capacity: pipeline.queue,
+            latches: LinkedList::new(),
+            depth,
+            latch_capacity: depth as usize * pipeline.fetch,
+            dispatch_stall: None,
+            stall_cycles: 0,
+            empty_cycles: 0,
+            rename_width_stalls: 0,
+        }
+    }
+
+    fn record(&self, stats: &mut Stats) {
+        stats.counter("stall_cycles", self.stall_cycles);
+        stats.counter("empty_cycles", self.empty_cycles);
+        stats.counter("rename_width_stalls", self.rename_width_stalls);
+    }
+
+    fn is_full(&self) -> bool {
+        self.instruction_q.len() + self.latches.len() >= self.capacity + self.latch_capacity
+    }
+
+    //Moves instructions that have made it through the front end stages into the queue
+    fn advance(&mut self) {
+        while self.latches.front().is_some_and(|&(cycles, _, _, _)| cycles == 0) {
+            let (_, seq, pc, instruction) = self.latches.pop_front().unwrap();
+            self.instruction_q.push_back((seq, pc, instruction));
+        }
+        for latch in self.latches.iter_mut() {
+            latch.0 -= 1;
+        }
+    }
+
+    fn finished(&self) -> bool {
+        self.instruction_q.is_empty() && self.latches.is_empty()
+    }
+
+    fn reset(&mut self) {
+        self.clear_instructions();
+    }
+
+    fn clear_instructions(&mut self) {
+        self.instruction_q.clear();
+        self.latches.clear();
+    }
+
+    fn add_instruction(&mut self, instruction: EncodedInstruction, pc: usize, seq: u64) {
+        if self.depth == 0 {
+            self.instruction_q.push_back((seq, pc, instruction));
+        } else {
+            self.latches.push_back((self.depth, seq, pc, instruction));
+        }
+    }
+
+    fn get_next_instruction(&self) -> Option<(u64, usize, EncodedInstruction)> {
+        match self.instruction_q.front() {
+            Some(x) => Some((*x).clone()),
+            None => None,
+        }
+    }
+
+    fn pop_instruction(&mut self) {
+        self.instruction_q.pop_front();
+    }
+
+    // Fetch sequence numbers of everything in the latches and queue
+    fn in_flight(&self) -> Vec<u64> {
+        self.latches.iter().map(|&(_, seq, _, _)| seq).chain(self.instruction_q.iter().map(|&(seq, _, _)| seq)).collect()
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum SelectPolicy {
+    Oldest,
+    Random,
+    Position,
+}
+
+impl SelectPolicy {
+    fn parse(name: &str) -> SelectPolicy {
+        match name.to_lowercase().as_str() {
+            "oldest" => SelectPolicy::Oldest,
+            "random" => SelectPolicy::Random,
+            "position" => SelectPolicy::Position,
+            _ => panic!("Unaccepted select policy {}", name),
+        }
+    }
+}
+
+// The size of each scheduler, with no functional unit type for a unified scheduler
+#[derive(Clone)]
+struct SchedulerConfig {
+    sizes: Vec<(Option<FUType>, usize)>,
+    select: SelectPolicy,
+}
+
+#[derive(Debug, Default, Copy, Clone)]
+struct SchedulerStats {
+    occupancy_sum: u64,
+    // Issue attempts that found every station in the scheduler busy
+    full_stalls: u64,
+    selected: u64,
+    // Stations left waiting at the end of dispatch with all operands ready
+    ready_not_selected: u64,
+}
+
+impl SchedulerStats {
+    fn record(&self, stats: &mut Stats) {
+        stats.counter("occupancy_sum", self.occupancy_sum);
+        stats.counter("full_stalls", self.full_stalls);
+        stats.counter("selected", self.selected);
+        stats.counter("ready_not_selected", self.ready_not_selected);
+    }
+}
+
+// A group of reservation stations, rs_sts[start..end], feeding one class of functional unit
+#[derive(Debug)]
+struct Scheduler {
+    fu_type: Option<FUType>,
+    start: usize,
+    end: usize,
+    stats: SchedulerStats,
+}
+
+impl Scheduler {
+    fn serves(&self, fu_type: FUType) -> bool {
+        self.fu_type.is_none_or(|t| t == fu_type)
+    }
+
+    fn name(&self) -> String {
+        match self.fu_type {
+            Some(t) => format!("{:?}", t),
+            None => String::from("Unified"),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum BusPriority {
+    // The result belonging to the oldest instruction wins
+    Age,
+    // Units win in the order they appear in the pool, the memory unit last
+    Unit,
+}
+
+impl BusPriority {
+    fn parse(name: &str) -> BusPriority {
+        match name.to_lowercase().as_str() {
+            "age" => BusPriority::Age,
+            "unit" => BusPriority::Unit,
+            _ => panic!("Unaccepted result bus priority {}", name),
+        }
+    }
+}
+
+#[derive(Debug, Default, Copy, Clone)]
+struct BusStats {
+    broadcasts: u64,
+    // Results held back a cycle as every bus was taken
+    conflicts: u64,
+    conflict_cycles: u64,
+}
+
+impl BusStats {
+    fn record(&self, stats: &mut Stats) {
+        stats.counter("broadcasts", self.broadcasts);
+        stats.counter("conflicts", self.conflicts);
+        stats.counter("conflict_cycles", self.conflict_cycles);
+    }
+}
+
+#[derive(Debug)]
+struct CommonDataBus {
+    // No limit when None
+    buses: Option<usize>,
+    priority: BusPriority,
+    stats: BusStats,
+}
+
+impl CommonDataBus {
+    fn new(buses: Option<usize>, priority: BusPriority) -> CommonDataBus {
+        if buses == Some(0) {
+            panic!("At least one result bus is needed");
+        }
+        CommonDataBus {
+            buses,
+            priority,
+            stats: BusStats::default(),
+        }
+    }
+
+    // Picks the units that broadcast this cycle from (unit, ROB entry, writes a register) requests
+    fn arbitrate(&mut self, mut requests: Vec<(Option<usize>, usize, bool)>, rob: &ReorderBuffer, ports: &mut RegisterPorts) -> Vec<Option<usize>> {
+        let buses = self.buses.unwrap_or(requests.len());
+        let mut writes = ports.write.unwrap_or(requests.len());
+        if self.priority == BusPriority::Age {
+            requests.sort_by_key(|&(_, rob_entry, _)| rob.age(rob_entry));
+        }
+
+        let mut granted = Vec::new();
+        let mut bus_conflicts = 0;
+        let mut port_conflicts = 0;
+        for (unit, _, writes_register) in requests {
+            if granted.len() == buses {
+                bus_conflicts += 1;
+                continue;
+            }
+            if writes_register {
+                if writes == 0 {
+                    port_conflicts += 1;
+                    continue;
                 }
-            },
+                writes -= 1;
+            }
+            granted.push(unit);
+        }
+
+        if bus_conflicts > 0 {
+            self.stats.conflicts += bus_conflicts;
+            self.stats.conflict_cycles += 1;
+        }
+        if port_conflicts > 0 {
+            ports.stats.write_conflicts += port_conflicts;
+            ports.stats.write_stall_cycles += 1;
         }
+        self.stats.broadcasts += granted.len() as u64;
+        granted
     }
+}
 
-    fn reset(&mut self) {
-        self.registers.clear_rat();
-        self.exec_unit.reset();
-        self.decode_unit.reset();
-        self.rob.empty();
-        self.lsq.clear();
+#[derive(Debug, Default, Copy, Clone)]
+struct PortStats {
+    read_stall_cycles: u64,
+    // Results held back a cycle as every write port was taken
+    write_conflicts: u64,
+    write_stall_cycles: u64,
+}
+
+impl PortStats {
+    fn record(&self, stats: &mut Stats) {
+        stats.counter("read_stall_cycles", self.read_stall_cycles);
+        stats.counter("write_conflicts", self.write_conflicts);
+        stats.counter("write_stall_cycles", self.write_stall_cycles);
     }
 }
 
+// Register file ports, with no limit when None
 #[derive(Debug)]
-struct FetchUnit {
-    width: usize,
-    pc: usize,
-    exec_pc: Option<usize>,
-    instructions: Vec<EncodedInstruction>,
-    stalled: bool,
-    reset: bool,
+struct RegisterPorts {
+    read: Option<usize>,
+    write: Option<usize>,
+    reads_used: usize,
+    // Whether a read port stall has been counted this cycle, as each thread can stall on them
+    read_stalled: bool,
+    stats: PortStats,
 }
 
-impl FetchUnit {
-    fn new(encoded_instructions: Vec<EncodedInstruction>, width: usize) -> FetchUnit {
-        FetchUnit {
-            width: width,
-            pc: 0,
-            exec_pc: None,
-            instructions: encoded_instructions,
-            stalled: false,
-            reset: false,
+impl RegisterPorts {
+    fn new(read: Option<usize>, write: Option<usize>) -> RegisterPorts {
+        if read.is_some_and(|r| r < 2) {
+            panic!("The register file needs at least 2 read ports to read both operands of an instruction");
         }
-    }
-
-    fn finished(&self) -> bool {
-        if self.pc < self.instructions.len() {
-            false
-        } else {
-            true
+        if write == Some(0) {
+            panic!("The register file needs at least one write port");
+        }
+        RegisterPorts {
+            read,
+            write,
+            reads_used: 0,
+            read_stalled: false,
+            stats: PortStats::default(),
         }
     }
 
-    fn speculate(&mut self, pc: usize) {
-        self.reset = true;
-        self.pc = pc;
-    }
-
-    fn mispredict(&mut self, new_pc: usize) {
-        self.reset = true;
-        self.pc = new_pc;
+    fn new_cycle(&mut self) {
+        self.reads_used = 0;
+        self.read_stalled = false;
     }
 
-    fn get_instruction(&self) -> EncodedInstruction {
-        if self.pc < self.instructions.len() {
-            self.instructions[self.pc]
-        } else {
-            EncodedInstruction::Halt
+    fn reserve_reads(&mut self, reads: usize) -> bool {
+        if let Some(ports) = self.read {
+            if self.reads_used + reads > ports {
+                if !self.read_stalled {
+                    self.stats.read_stall_cycles += 1;
+                    self.read_stalled = true;
+                }
+                return false;
+            }
         }
+        self.reads_used += reads;
+        true
     }
 }
 
+// Forwarding paths from unit outputs back to waiting instructions
 #[derive(Debug)]
-struct DecodeUnit {
-    instruction_q: LinkedList<(usize, EncodedInstruction)>,
-    stalled: bool,
-    reset: bool,
+struct BypassNetwork {
+    // Units with a forwarding path, None in the list being the memory unit, or every unit when None
+    paths: Option<Vec<Option<FUType>>>,
+    // Extra cycles for a result to reach waiting instructions through the register file
+    latency: u32,
+    pending: Vec<(u32, ExecResult, usize)>,
+    delayed: u64,
 }
 
-impl DecodeUnit {
-    fn new() -> DecodeUnit {
-        DecodeUnit {
-            instruction_q: LinkedList::new(),
-            stalled: false,
-            reset: false,
+impl BypassNetwork {
+    fn new(paths: Option<Vec<Option<FUType>>>, latency: u32) -> BypassNetwork {
+        if latency == 0 {
+            panic!("Results without a bypass path need at least one extra cycle");
+        }
+        BypassNetwork {
+            paths,
+            latency,
+            pending: Vec::new(),
+            delayed: 0,
         }
     }
 
-    fn finished(&self) -> bool {
-        self.instruction_q.is_empty()
-    }
-
-    fn reset(&mut self) {
-        self.clear_instructions();
+    // Reads a comma separated list of units such as "alu,mem", or "none"
+    fn parse_paths(list: &str) -> Vec<Option<FUType>> {
+        if list.to_lowercase() == "none" {
+            return Vec::new();
+        }
+        list.split(',').map(|unit| {
+            match unit.to_lowercase().as_str() {
+                "mem" | "memory" => None,
+                other => Some(FUType::parse(other)),
+            }
+        }).collect()
     }
 
-    fn clear_instructions(&mut self) {
-        self.instruction_q.clear();
+    fn forwards(&self, source: Option<FUType>) -> bool {
+        self.paths.as_ref().is_none_or(|paths| paths.contains(&source))
     }
 
-    fn add_instruction(&mut self, instruction: EncodedInstruction, pc: usize) {
-        self.instruction_q.push_back((pc, instruction));
+    fn delay(&mut self, result: ExecResult, rob_entry: usize) {
+        self.delayed += 1;
+        self.pending.push((self.latency, result, rob_entry));
     }
 
-    fn get_next_instruction(&self) -> Option<(usize, EncodedInstruction)> {
-        match self.instruction_q.front() {
-            Some(x) => Some((*x).clone()),
-            None => None,
+    fn due(&mut self) -> Vec<(ExecResult, usize)> {
+        let mut due = Vec::new();
+        for &mut (ref mut cycles, result, rob_entry) in &mut self.pending {
+            *cycles -= 1;
+            if *cycles == 0 {
+                due.push((result, rob_entry));
+            }
         }
-    }
-
-    fn pop_instruction(&mut self) {
-        self.instruction_q.pop_front();
+        self.pending.retain(|&(cycles, _, _)| cycles > 0);
+        due
     }
 }
 
 struct ExecUnit {
     func_units: Vec<FunctionalUnit>,
     rs_sts: Vec<ReservationStation>,
+    schedulers: Vec<Scheduler>,
+    select: SelectPolicy,
+    cdb: CommonDataBus,
+    ports: RegisterPorts,
+    bypass: BypassNetwork,
+    // Cycles a newly issued station spends in the dispatch stages
+    dispatch_delay: u32,
     mem_unit: MemoryUnit,
 }
 
@@ -661,67 +2871,206 @@ impl fmt::Debug for ExecUnit {
 }
 
 impl ExecUnit {
-    fn new(num_rs: usize) -> ExecUnit {
+    fn new(pool: &[FUConfig], config: SchedulerConfig, cdb: CommonDataBus, ports: RegisterPorts, bypass: BypassNetwork) -> ExecUnit {
         let mut fus: Vec<FunctionalUnit> = Vec::new();
-
-        //ALUs
-        for _ in 0..NUM_ALUS {
-            fus.push(FunctionalUnit::new(FUType::ALU));
-        }
-        for _ in 0..NUM_MULTS {
-            fus.push(FunctionalUnit::new(FUType::Multiplier));
+        for unit in pool {
+            for _ in 0..unit.count {
+                fus.push(FunctionalUnit::new(unit));
+            }
         }
 
-        fus.push(FunctionalUnit::new(FUType::Branch));
-        fus.push(FunctionalUnit::new(FUType::Branch));
-        
-
         let mut rs_sts: Vec<ReservationStation> = Vec::new();
-        for _ in 0..num_rs {
-            rs_sts.push(ReservationStation::new());
+        let mut schedulers = Vec::new();
+        for (fu_type, size) in config.sizes {
+            if size == 0 {
+                panic!("Every scheduler needs at least one reservation station");
+            }
+            let start = rs_sts.len();
+            for _ in 0..size {
+                rs_sts.push(ReservationStation::new());
+            }
+            schedulers.push(Scheduler {
+                fu_type,
+                start,
+                end: rs_sts.len(),
+                stats: SchedulerStats::default(),
+            });
         }
         ExecUnit {
             func_units: fus,
             rs_sts: rs_sts,
+            schedulers,
+            select: config.select,
+            cdb,
+            ports,
+            bypass,
+            dispatch_delay: 0,
             mem_unit: MemoryUnit::new(),
         }
     }
 
-    fn reset(&mut self) {
+    fn record(&self, stats: &mut Stats) {
+        for scheduler in &self.schedulers {
+            stats.enter(&format!("{}_rs", stats::key(&scheduler.name())));
+            scheduler.stats.record(stats);
+            stats.leave();
+        }
+        for (i, fu) in self.func_units.iter().enumerate() {
+            stats.enter(&format!("fu{}", i));
+            fu.record(stats);
+            stats.leave();
+        }
+        stats.enter("cdb");
+        self.cdb.stats.record(stats);
+        stats.leave();
+        stats.enter("register_ports");
+        self.ports.stats.record(stats);
+        stats.leave();
+        stats.counter("bypass_delayed", self.bypass.delayed);
+    }
+
+    //Only the mispredicting thread's work is squashed
+    fn reset(&mut self, thread: usize) {
         for rs in &mut self.rs_sts {
-            rs.free();
+            if rs.busy && ReorderBuffer::thread_of(rs.rob_entry) == thread {
+                rs.free();
+            }
         }
         for fu in &mut self.func_units {
-            fu.reset();
+            fu.reset(thread);
         }
+        self.bypass.pending.retain(|&(_, _, rob_entry)| ReorderBuffer::thread_of(rob_entry) != thread);
+        self.mem_unit.reset(thread);
+    }
+
+    // Instructions of a thread waiting in the reservation stations
+    fn occupancy(&self, thread: usize) -> usize {
+        self.rs_sts.iter().filter(|rs| rs.busy && ReorderBuffer::thread_of(rs.rob_entry) == thread).count()
     }
 
     fn finished(&self) -> bool {
-        self.func_units.iter().all(|ref x| x.finished()) && self.rs_sts.iter().all(|ref x| x.finished() && self.mem_unit.finished())
+        self.func_units.iter().all(|ref x| x.finished()) && self.rs_sts.iter().all(|ref x| x.finished() && self.mem_unit.finished()) && self.bypass.pending.is_empty()
     }
 
-    fn get_free_rs(&self) -> Option<usize> {
-        for rs in 0..self.rs_sts.len() {
+    fn scheduler_of(&self, op: Op) -> usize {
+        let fu_type = match self.func_units.iter().find(|fu| fu.supports(op)) {
+            Some(fu) => fu.fu_type,
+            None => panic!("No functional unit supports {:?}", op),
+        };
+        self.schedulers.iter().position(|s| s.serves(fu_type)).expect("No scheduler for operation")
+    }
+
+    fn get_free_rs(&mut self, op: Op) -> Option<usize> {
+        let scheduler = self.scheduler_of(op);
+        for rs in self.schedulers[scheduler].start..self.schedulers[scheduler].end {
             if !self.rs_sts[rs].busy {
                 return Some(rs);
             }
         }
+        self.schedulers[scheduler].stats.full_stalls += 1;
         return None;
     }
 
+    fn scheduler_full(&self, op: Op) -> bool {
+        let scheduler = &self.schedulers[self.scheduler_of(op)];
+        self.rs_sts[scheduler.start..scheduler.end].iter().all(|rs| rs.busy)
+    }
+
+    // Whether a ROB entry is a division or remainder waiting for or using a unit
+    fn divides(&self, rob_entry: usize) -> bool {
+        let divide = |op: Op| op == Op::Div || op == Op::Mod;
+        self.rs_sts.iter().any(|rs| rs.busy && rs.rob_entry == rob_entry && divide(rs.operation)) ||
+        self.func_units.iter().any(|fu| fu.in_flight.iter().any(|op| op.rob_entry == rob_entry && divide(op.operation)))
+    }
+
+    //Each functional unit takes at most one ready station from its scheduler per cycle, returning the ROB entries issued
+    fn dispatch(&mut self, rob: &ReorderBuffer) -> Vec<usize> {
+        let mut issued = Vec::new();
+        for fu in 0..self.func_units.len() {
+            let fu_type = self.func_units[fu].fu_type;
+            let scheduler = self.schedulers.iter().position(|s| s.serves(fu_type)).expect("No scheduler for functional unit");
+            let candidates: Vec<usize> = (self.schedulers[scheduler].start..self.schedulers[scheduler].end)
+                .filter(|&rs| self.rs_sts[rs].busy && self.rs_sts[rs].wait == 0 && self.rs_sts[rs].get_operands().is_some() && self.func_units[fu].supports(self.rs_sts[rs].operation))
+                .collect();
+            if let Some(rs) = self.select(&candidates, rob) {
+                let (x, y) = self.rs_sts[rs].get_operands().unwrap();
+                if self.func_units[fu].dispatch(x, y, self.rs_sts[rs].operation, self.rs_sts[rs].rob_entry, self.rs_sts[rs].address) {
+                    issued.push(self.rs_sts[rs].rob_entry);
+                    self.rs_sts[rs].free();
+                    self.schedulers[scheduler].stats.selected += 1;
+                }
+            }
+        }
+
+        for scheduler in &mut self.schedulers {
+            for rs in &self.rs_sts[scheduler.start..scheduler.end] {
+                if rs.busy {
+                    scheduler.stats.occupancy_sum += 1;
+                    if rs.wait == 0 && rs.get_operands().is_some() {
+                        scheduler.stats.ready_not_selected += 1;
+                    }
+                }
+            }
+        }
+        for rs in &mut self.rs_sts {
+            if rs.wait > 0 {
+                rs.wait -= 1;
+            }
+        }
+        issued
+    }
+
+    fn select(&self, candidates: &[usize], rob: &ReorderBuffer) -> Option<usize> {
+        if candidates.is_empty() {
+            return None;
+        }
+        match self.select {
+            SelectPolicy::Position => Some(candidates[0]),
+            SelectPolicy::Oldest => {
+                candidates.iter().cloned().min_by_key(|&rs| rob.age(self.rs_sts[rs].rob_entry))
+            },
+            SelectPolicy::Random => Some(candidates[rand::thread_rng().gen_range(0, candidates.len())]),
+        }
+    }
+
     fn issue(&mut self, o1: Operand, o2: Operand, operation: Op, rs: usize, rob_entry: usize) {
         self.rs_sts[rs].issue(o1, o2, operation, rob_entry);
+        self.rs_sts[rs].wait = self.dispatch_delay;
     }
 
     fn issue_branch(&mut self, o1: Operand, o2: Operand, operation: Op, rs: usize, rob_entry: usize, addr: usize) {
         self.rs_sts[rs].issue_branch(o1, o2, operation, rob_entry, addr);
+        self.rs_sts[rs].wait = self.dispatch_delay;
     }
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Consistency {
+    // Loads wait behind every older store so memory operations reach memory in program order
+    Sequential,
+    // Loads pass committed stores still waiting in the store buffer, taking the value of one to the same address
+    Tso,
+}
+
+impl Consistency {
+    fn parse(name: &str) -> Consistency {
+        match name.to_lowercase().as_str() {
+            "sc" => Consistency::Sequential,
+            "tso" => Consistency::Tso,
+            _ => panic!("Unaccepted consistency model {}", name),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
 enum LSQOp {
     L,
     S,
+    // Load linked and store conditional, which only go to memory once they are the oldest instruction
+    LL,
+    SC,
+    // Holds back younger memory operations until older stores have finished
+    Fence,
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -732,10 +3081,12 @@ struct LSQEntry {
     addr: Operand,
     value: Operand,
     committed: bool,
+    // Cycles left in the dispatch stages before the entry can go to memory
+    wait: u32,
 }
 
 impl LSQEntry {
-    fn new(op: LSQOp, pc: usize, rob_entry: usize, addr: Operand, value: Operand) -> LSQEntry {
+    fn new(op: LSQOp, pc: usize, rob_entry: usize, addr: Operand, value: Operand, wait: u32) -> LSQEntry {
         LSQEntry {
             op: op,
             pc: pc,
@@ -743,19 +3094,48 @@ impl LSQEntry {
             addr: addr,
             value: value,
             committed: false,
+            wait,
         }
     }
 }
 
+// Committed stores stay at the front of the queue until they reach memory, so they double as the store buffer
 #[derive(Debug)]
 struct LSQ {
     lsq: LinkedList<LSQEntry>,
+    dispatch_delay: u32,
+    capacity: Option<usize>,
+    model: Consistency,
+    // Loads that went to memory ahead of older buffered stores, and those that took a buffered store's value
+    bypassed_loads: u64,
+    forwarded_loads: u64,
 }
 
 impl LSQ {
-    fn new() -> LSQ {
+    fn new(dispatch_delay: u32, capacity: Option<usize>, model: Consistency) -> LSQ {
+        if capacity == Some(0) {
+            panic!("The load store queue needs at least one entry");
+        }
         LSQ {
              lsq: LinkedList::new(),
+             dispatch_delay,
+             capacity,
+             model,
+             bypassed_loads: 0,
+             forwarded_loads: 0,
+        }
+    }
+
+    fn record(&self, stats: &mut Stats) {
+        stats.counter("bypassed_loads", self.bypassed_loads);
+        stats.counter("forwarded_loads", self.forwarded_loads);
+    }
+
+    fn tick(&mut self) {
+        for entry in self.lsq.iter_mut() {
+            if entry.wait > 0 {
+                entry.wait -= 1;
+            }
         }
     }
 
@@ -763,6 +3143,15 @@ impl LSQ {
         self.lsq.len() == 0
     }
 
+    fn is_full(&self) -> bool {
+        self.capacity.is_some_and(|capacity| self.lsq.len() >= capacity)
+    }
+
+    // Committed stores are skipped as their ROB entries may have been reused
+    fn holds(&self, rob_entry: usize) -> bool {
+        self.lsq.iter().any(|entry| !entry.committed && entry.rob_entry == rob_entry)
+    }
+
     fn clear(&mut self) {
         
         while let Some(back) = self.lsq.pop_back() {
@@ -774,19 +3163,19 @@ impl LSQ {
     }
 
     fn issue(&mut self, op: LSQOp, pc: usize, rob_entry: usize, addr: Operand, value: Operand) {
-        self.lsq.push_back(LSQEntry::new(op, pc, rob_entry, addr, value));
+        self.lsq.push_back(LSQEntry::new(op, pc, rob_entry, addr, value, self.dispatch_delay));
     }
 
     fn resolve_dependency(&mut self, result: u32, rob_entry: usize) {
         for entry in self.lsq.iter_mut() {
             // If the address of a load or store is dependign on an execution result
-            if let Operand::Rob(r) = entry.addr {
+            if let Operand::Tag(r) = entry.addr {
                 if r == rob_entry {
                     entry.addr = Operand::Value(result);
                 }
             }
             //If a store is depending on a register result
-            if let Operand::Rob(r) = entry.value {
+            if let Operand::Tag(r) = entry.value {
                 if r == rob_entry {
                     entry.value = Operand::Value(result);
                 }
@@ -794,10 +3183,19 @@ impl LSQ {
         }
     }
 
-    fn get_next_instruction(&mut self) -> Option<LSQEntry> {
+    // Head is the thread's oldest ROB entry and drained whether its older stores have all finished
+    fn get_next_instruction(&mut self, head: usize, drained: bool) -> Option<LSQEntry> {
+        if self.model == Consistency::Tso {
+            if let Some(load) = self.bypass() {
+                return Some(load);
+            }
+        }
         match self.lsq.pop_front() {
             Some(instruction) => {
                 self.lsq.push_front(instruction);
+                if instruction.wait > 0 {
+                    return None;
+                }
                 match instruction.op {
                     LSQOp::S => {
                         if instruction.committed {
@@ -814,13 +3212,55 @@ impl LSQ {
                             self.lsq.pop_front();
                             Some((instruction).clone())
                         } else { None }
-                    }
+                    },
+                    //Atomics never run down a wrong path and see every older store
+                    LSQOp::LL | LSQOp::SC => {
+                        let operands = match (instruction.addr, instruction.value) {
+                            (Operand::Value(_), Operand::Value(_)) => true,
+                            (Operand::Value(_), Operand::None) => instruction.op == LSQOp::LL,
+                            _ => false,
+                        };
+                        if operands && instruction.rob_entry == head && drained {
+                            self.lsq.pop_front();
+                            Some(instruction)
+                        } else { None }
+                    },
+                    LSQOp::Fence => {
+                        if drained {
+                            self.lsq.pop_front();
+                            Some(instruction)
+                        } else { None }
+                    },
                 }
             },
             None => None,
         }
     }
 
+    // The first load behind a run of buffered stores, carrying the value of the youngest one it overlaps
+    fn bypass(&mut self) -> Option<LSQEntry> {
+        let mut buffered = Vec::new();
+        for entry in self.lsq.iter() {
+            match (entry.op, entry.committed, entry.addr, entry.value) {
+                (LSQOp::S, true, Operand::Value(addr), Operand::Value(value)) => buffered.push((addr, value)),
+                (LSQOp::L, _, Operand::Value(addr), _) if !buffered.is_empty() && entry.wait == 0 => {
+                    let mut load = *entry;
+                    if let Some(&(_, value)) = buffered.iter().rev().find(|&&(a, _)| a == addr) {
+                        load.value = Operand::Value(value);
+                        self.forwarded_loads += 1;
+                    }
+                    self.bypassed_loads += 1;
+                    let mut rest = self.lsq.split_off(buffered.len());
+                    rest.pop_front();
+                    self.lsq.append(&mut rest);
+                    return Some(load);
+                },
+                _ => return None,
+            }
+        }
+        None
+    }
+
     fn committed(&mut self, rob_entry: usize) {
         for entry in self.lsq.iter_mut() {
             if rob_entry == entry.rob_entry {
@@ -830,80 +3270,206 @@ impl LSQ {
     }
 }
 
-#[derive(Debug)]
-struct MemoryUnit {
+#[derive(Debug, Copy, Clone)]
+struct MemoryAccess {
     instruction: LSQEntry,
     cycles: u32,
-    result: Option<u32>,
+    value: u32,
+}
+
+#[derive(Debug)]
+struct MemoryUnit {
+    pending: Option<LSQEntry>,
+    in_flight: Vec<MemoryAccess>,
+    results: LinkedList<(usize, u32)>,
+    model: Consistency,
+    // L1 data cache misses by thread and pc
+    misses: HashMap<(usize, usize), u64>,
 }
 
 impl MemoryUnit {
 
     fn new() -> MemoryUnit {
         MemoryUnit {
-            instruction: LSQEntry::new(LSQOp::S, 0, 0, Operand::None, Operand::None),
-            cycles: 0,
-            result: None,
+            pending: None,
+            in_flight: Vec::new(),
+            results: LinkedList::new(),
+            model: Consistency::Sequential,
+            misses: HashMap::new(),
         }
     }
 
     fn finished(&self) -> bool {
-        if let None = self.result {
-            if self.cycles == 0{
-                true
-            } else { false }
-            
-        } else { false }
+        self.pending.is_none() && self.in_flight.is_empty() && self.results.is_empty()
+    }
+
+    fn can_accept(&self) -> bool {
+        self.pending.is_none()
     }
 
     fn dispatch(&mut self, next_instruction: LSQEntry) -> bool {
-        if let None = self.result {
-            if self.cycles == 0 {
-                self.instruction = next_instruction;
-                self.result = None;
-                self.cycles = 2;
-                true
-            } else { false }
+        if self.can_accept() {
+            self.pending = Some(next_instruction);
+            true
         } else { false }
     }
 
-    fn cycle(&mut self, memory: &mut [u32; MEM_SIZE]) {
-        if self.cycles > 0 {
-            self.cycles -= 1;
-            if self.cycles == 0 {
-                match self.instruction.op {
-                    LSQOp::S => {
-                        if let Operand::Value(value) = self.instruction.value {
-                            if let Operand::Value(addr) = self.instruction.addr {
-                                memory[addr as usize] = value;
-                            } else { panic!("Dispatched store without knowing the address {:?}", self.instruction.addr); }
-                            
-                        } else { panic!("Dispatched store without knowing the value {:?}", self.instruction.value); }
-                    },
-                    LSQOp::L => {
-                        if let Operand::Value(addr) = self.instruction.addr {
-                            self.result = Some(memory[addr as usize]);
-                        }
+    // Loads are younger than the mispredicted branch so are dropped, stores have already committed
+    // and atomics only start once nothing older can mispredict
+    fn reset(&mut self, thread: usize) {
+        let squashed = |instruction: &LSQEntry| match instruction.op {
+            LSQOp::L => ReorderBuffer::thread_of(instruction.rob_entry) == thread,
+            _ => false,
+        };
+        if self.pending.as_ref().is_some_and(squashed) {
+            self.pending = None;
+        }
+        self.in_flight.retain(|a| !squashed(&a.instruction));
+        self.results = self.results.iter().cloned().filter(|&(rob_entry, _)| ReorderBuffer::thread_of(rob_entry) != thread).collect();
+    }
+
+    fn stores_drained(&self, thread: usize) -> bool {
+        let store = |instruction: &LSQEntry| instruction.op == LSQOp::S && ReorderBuffer::thread_of(instruction.rob_entry) == thread;
+        !self.pending.as_ref().is_some_and(store) && !self.in_flight.iter().any(|a| store(&a.instruction))
+    }
+
+    // Whether a load, atomic or fence is being handled, stores having committed before they get here
+    fn holds(&self, rob_entry: usize) -> bool {
+        let access = |instruction: &LSQEntry| instruction.op != LSQOp::S && instruction.rob_entry == rob_entry;
+        self.pending.as_ref().is_some_and(access) ||
+        self.in_flight.iter().any(|a| access(&a.instruction)) ||
+        self.results.iter().any(|&(entry, _)| entry == rob_entry)
+    }
+
+    // The youngest store of a thread to an address still on its way to memory
+    fn in_flight_store(&self, thread: usize, addr: usize) -> Option<u32> {
+        self.in_flight.iter().rev().find(|a| {
+            a.instruction.op == LSQOp::S && ReorderBuffer::thread_of(a.instruction.rob_entry) == thread &&
+            match a.instruction.addr { Operand::Value(x) => x as usize == addr, _ => false }
+        }).map(|a| a.value)
+    }
+
+    // The value is read or written as the access starts so in flight accesses cannot reorder,
+    // except under TSO where stores only reach memory once their access finishes
+    fn start_access(&mut self, core: usize, instruction: LSQEntry, memories: &mut [[u32; MEM_SIZE]], mem_system: &mut MemorySystem) -> bool {
+        //A load forwarded from the store buffer never reaches the cache
+        if let (LSQOp::L, Operand::Value(value)) = (instruction.op, instruction.value) {
+            self.in_flight.push(MemoryAccess {
+                instruction,
+                cycles: 1,
+                value,
+            });
+            return true;
+        }
+        let thread = ReorderBuffer::thread_of(instruction.rob_entry);
+        let memory = &mut memories[thread];
+        let addr = match instruction.addr {
+            Operand::Value(addr) => addr as usize,
+            _ => panic!("Dispatched memory operation without knowing the address {:?}", instruction.addr),
+        };
+        let offset = thread * THREAD_ADDRESS_SPACE;
+        //A store conditional that has lost its reservation fails without writing
+        let linked = instruction.op == LSQOp::SC && mem_system.linked(core, thread, offset + addr);
+        let write = match instruction.op {
+            LSQOp::S => true,
+            LSQOp::SC => linked,
+            _ => false,
+        };
+        let misses = mem_system.l1d_misses(core);
+        let latency = match mem_system.access_data(core, offset + addr, write, offset + instruction.pc) {
+            Some(latency) => latency,
+            None => return false,
+        };
+        if mem_system.l1d_misses(core) != misses {
+            *self.misses.entry((thread, instruction.pc)).or_insert(0) += 1;
+        }
+
+        let value = match instruction.op {
+            LSQOp::S | LSQOp::SC if write => {
+                if let Operand::Value(value) = instruction.value {
+                    if instruction.op == LSQOp::SC || self.model == Consistency::Sequential {
+                        memory[addr] = value;
                     }
+                    if instruction.op == LSQOp::SC { 1 } else { value }
+                } else { panic!("Dispatched store without knowing the value {:?}", instruction.value); }
+            },
+            LSQOp::SC => 0,
+            LSQOp::LL => {
+                mem_system.link(core, thread, offset + addr);
+                memory[addr]
+            },
+            _ if self.model == Consistency::Tso => self.in_flight_store(thread, addr).unwrap_or(memory[addr]),
+            _ => memory[addr],
+        };
+        if instruction.op == LSQOp::SC {
+            mem_system.unlink(core, thread);
+        }
+        self.in_flight.push(MemoryAccess {
+            instruction,
+            cycles: if latency == 0 { 1 } else { latency },
+            value,
+        });
+        true
+    }
+
+    fn cycle(&mut self, core: usize, memories: &mut [[u32; MEM_SIZE]], mem_system: &mut MemorySystem) {
+        if let Some(instruction) = self.pending {
+            if self.start_access(core, instruction, memories, mem_system) {
+                self.pending = None;
+            }
+        }
+
+        //Buffered stores reach memory in program order so one that finishes early waits for older ones
+        let mut blocked = Vec::new();
+        for access in &mut self.in_flight {
+            let thread = ReorderBuffer::thread_of(access.instruction.rob_entry);
+            let buffered = access.instruction.op == LSQOp::S && self.model == Consistency::Tso;
+            if buffered && blocked.contains(&thread) {
+                if access.cycles > 1 {
+                    access.cycles -= 1;
+                }
+                continue;
+            }
+            access.cycles -= 1;
+            if access.cycles == 0 {
+                match access.instruction.op {
+                    LSQOp::S if buffered => {
+                        if let Operand::Value(addr) = access.instruction.addr {
+                            memories[thread][addr as usize] = access.value;
+                        }
+                    },
+                    LSQOp::S => (),
+                    _ => self.results.push_back((access.instruction.rob_entry, access.value)),
                 }
+            } else if buffered {
+                blocked.push(thread);
             }
         }
+        self.in_flight.retain(|a| a.cycles > 0);
     }
 
-    fn get_result(&mut self) -> Option<(usize, ExecResult)> {
-        if let Some(result) = self.result {
-            if self.cycles == 0 {
-                self.result = None;
-                Some((self.instruction.rob_entry, ExecResult::Value(result)))
-            } else { None }
-            
+    // Accesses under way for the timeline, or results waiting for a result bus once none are left
+    fn activity(&self) -> Option<String> {
+        if !self.in_flight.is_empty() {
+            let accesses: Vec<String> = self.in_flight.iter().map(|a| format!("{:?}", a.instruction.op)).collect();
+            Some(accesses.join(" "))
+        } else if !self.results.is_empty() {
+            Some(String::from("Result waiting"))
         } else {
             None
         }
     }
+
+    fn peek_result(&self) -> Option<usize> {
+        self.results.front().map(|&(rob_entry, _)| rob_entry)
+    }
+
+    fn get_result(&mut self) -> Option<(usize, ExecResult)> {
+        self.results.pop_front().map(|(rob_entry, value)| (rob_entry, ExecResult::Value(value)))
+    }
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 enum Op {
     None,
     Add,
@@ -923,366 +3489,330 @@ enum Op {
     Bgt,
 }
 
-#[derive(Debug, Copy, Clone)]
+impl Op {
+    fn parse(name: &str) -> Op {
+        match name.to_lowercase().as_str() {
+            "add" => Op::Add,
+            "and" => Op::And,
+            "or" => Op::Or,
+            "sub" => Op::Sub,
+            "xor" => Op::Xor,
+            "mov" => Op::Mov,
+            "sr" => Op::Sr,
+            "sl" => Op::Sl,
+            "mult" => Op::Mult,
+            "div" => Op::Div,
+            "mod" => Op::Mod,
+            "beq" => Op::Beq,
+            "beqz" => Op::Beqz,
+            "blt" => Op::Blt,
+            "bgt" => Op::Bgt,
+            _ => panic!("Unaccepted operation {}", name),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
 enum FUType {
     Multiplier,
     ALU,
     Branch,
 }
 
-#[derive(Debug)]
-struct FunctionalUnit {
+impl FUType {
+    fn parse(name: &str) -> FUType {
+        match name.to_lowercase().as_str() {
+            "alu" => FUType::ALU,
+            "mult" | "multiplier" => FUType::Multiplier,
+            "branch" => FUType::Branch,
+            _ => panic!("Unaccepted functional unit type {}", name),
+        }
+    }
+}
+
+// How often a functional unit can start a new operation
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Pipelining {
+    Pipelined,
+    // Each operation has the unit to itself until it finishes
+    Unpipelined,
+    Interval(u32),
+}
+
+impl Pipelining {
+    fn parse(name: &str) -> Pipelining {
+        let name = name.to_lowercase();
+        match name.as_str() {
+            "pipelined" => Pipelining::Pipelined,
+            "unpipelined" => Pipelining::Unpipelined,
+            _ if name.starts_with("interval:") => {
+                match name["interval:".len()..].parse::<u32>() {
+                    Ok(interval) if interval > 0 => Pipelining::Interval(interval),
+                    _ => panic!("Unaccepted initiation interval {}", name),
+                }
+            },
+            _ => panic!("Unaccepted pipelining {}", name),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct FUConfig {
     fu_type: FUType,
+    count: usize,
+    pipelining: Pipelining,
+    latencies: Vec<(Op, u32)>,
+}
+
+impl FUConfig {
+    fn defaults() -> Vec<FUConfig> {
+        vec![
+            FUConfig {
+                fu_type: FUType::ALU,
+                count: 3,
+                pipelining: Pipelining::Pipelined,
+                latencies: vec![(Op::Add, 1), (Op::And, 1), (Op::Or, 1), (Op::Sub, 1), (Op::Xor, 1), (Op::Mov, 1), (Op::Sl, 1), (Op::Sr, 1)],
+            },
+            FUConfig {
+                fu_type: FUType::Multiplier,
+                count: 2,
+                pipelining: Pipelining::Unpipelined,
+                latencies: vec![(Op::Mult, 2), (Op::Div, 3), (Op::Mod, 3)],
+            },
+            FUConfig {
+                fu_type: FUType::Branch,
+                count: 2,
+                pipelining: Pipelining::Pipelined,
+                latencies: vec![(Op::Beq, 1), (Op::Beqz, 1), (Op::Blt, 1), (Op::Bgt, 1)],
+            },
+        ]
+    }
+
+    // One kind of unit per line: <type> <count> <pipelined|unpipelined|interval:N> <op>:<latency> ...
+    // e.g. "mult 1 unpipelined div:12 mod:12", with # starting a comment
+    fn load(path: &str) -> Vec<FUConfig> {
+        let file = File::open(path).expect("Could not open functional unit configuration");
+        let mut pool = Vec::new();
+        for line in BufReader::new(file).lines() {
+            let line = line.expect("Could not parse line");
+            let fields: Vec<&str> = line.split('#').next().unwrap().split_whitespace().collect();
+            if fields.is_empty() {
+                continue;
+            }
+            if fields.len() < 4 {
+                panic!("Functional unit needs a type, count, pipelining and at least one operation: {}", line);
+            }
+            let mut latencies = Vec::new();
+            for op in &fields[3..] {
+                let parts: Vec<&str> = op.split(':').collect();
+                let latency = match parts.get(1).map(|l| l.parse::<u32>()) {
+                    Some(Ok(latency)) if latency > 0 => latency,
+                    _ => panic!("Operation needs a non zero latency, got {}", op),
+                };
+                latencies.push((Op::parse(parts[0]), latency));
+            }
+            pool.push(FUConfig {
+                fu_type: FUType::parse(fields[0]),
+                count: fields[1].parse::<usize>().expect("Functional unit count must be a number"),
+                pipelining: Pipelining::parse(fields[2]),
+                latencies,
+            });
+        }
+        //Every operation the defaults provide for needs a unit, or a program using it would stall forever
+        for op in FUConfig::defaults().iter().flat_map(|config| config.latencies.iter().map(|&(op, _)| op)) {
+            if !pool.iter().any(|config| config.count > 0 && config.latencies.iter().any(|&(o, _)| o == op)) {
+                panic!("Functional unit configuration {} has no unit for {:?}", path, op);
+            }
+        }
+        pool
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+struct FUOperation {
     op1: u32,
     op2: u32,
     addr: usize,
     operation: Op,
-    cycles: i32,
-    result: Option<(usize, ExecResult)>,
     rob_entry: usize,
-    op1_next: u32,
-    op2_next: u32,
-    addr_next: usize,
-    operation_next: Op,
-    rob_entry_next: usize,
+    cycles: u32,
 }
 
-impl FunctionalUnit {
-    fn new(fu_type: FUType) -> FunctionalUnit {
-        FunctionalUnit {
-            fu_type: fu_type,
-            op1: 0,
-            op2: 0,
-            addr: 0,
-            operation: Op::None,
-            cycles: 0,
-            result: None,
-            rob_entry: 0,
-            op1_next: 0,
-            op2_next: 0,
-            addr_next: 0,
-            operation_next: Op::None,
-            rob_entry_next: 0,
+#[derive(Debug)]
+struct FunctionalUnit {
+    fu_type: FUType,
+    pipelining: Pipelining,
+    latencies: Vec<(Op, u32)>,
+    in_flight: Vec<FUOperation>,
+    // Cycles until another operation can start
+    issue_wait: u32,
+    results: LinkedList<(usize, ExecResult)>,
+    operations: u64,
+    busy_cycles: u64,
+    // Cycles frozen holding a result that lost result bus arbitration
+    writeback_stall_cycles: u64,
+}
+
+impl FunctionalUnit {
+    fn new(config: &FUConfig) -> FunctionalUnit {
+        FunctionalUnit {
+            fu_type: config.fu_type,
+            pipelining: config.pipelining,
+            latencies: config.latencies.clone(),
+            in_flight: Vec::new(),
+            issue_wait: 0,
+            results: LinkedList::new(),
+            operations: 0,
+            busy_cycles: 0,
+            writeback_stall_cycles: 0,
         }
     }
 
+    fn record(&self, stats: &mut Stats) {
+        stats.counter("operations", self.operations);
+        stats.counter("busy_cycles", self.busy_cycles);
+        stats.counter("writeback_stall_cycles", self.writeback_stall_cycles);
+    }
+
+    fn latency(&self, operation: Op) -> Option<u32> {
+        self.latencies.iter().find(|&&(op, _)| op == operation).map(|&(_, latency)| latency)
+    }
+
+    fn supports(&self, operation: Op) -> bool {
+        self.latency(operation).is_some()
+    }
+
     fn dispatch(&mut self, o1: u32, o2: u32, operation: Op, rob_entry: usize, addr: usize) -> bool {
-        if self.cycles > 1 {
-            if let Op::None = self.operation_next {
-                return false;
-            }
+        if self.issue_wait > 0 {
+            return false;
         }
-        let correct_type = match self.fu_type {
-            FUType::ALU => {
-                match operation {
-                    Op::Add => {
-                        true
-                    },
-                    Op::And => {
-                        true
-                    },
-                    Op::Or => {
-                        true
-                    },
-                    Op::Sub => {
-                        true
-                    }
-                    Op::Xor => {
-                        true
-                    }
-                    Op::Mov => {
-                        true
-                    }
-                    Op::Sl => {
-                        true
-                    }
-                    Op::Sr => {
-                        true
-                    }
-                    _ => {
-                        false
-                    }
-                }
-            },
-            FUType::Multiplier => {
-                match operation {
-                    Op::Mult => {
-                        true
-                    },
-                    Op::Div => {
-                        true
-                    },
-                    Op::Mod => {
-                        true
-                    }
-                    _ => false,
-                }
-            },
-            FUType::Branch => {
-                match operation {
-                    Op::Beq => {
-                        true
-                    },
-                    Op::Beqz => {
-                        true
-                    },
-                    Op::Blt => {
-                        true
-                    },
-                    Op::Bgt => {
-                        true
-                    }
-                    _ => false,
-                }
-            },
+        let latency = match self.latency(operation) {
+            Some(latency) => latency,
+            None => return false,
         };
-        if correct_type {
-            if self.cycles == 0 {
-                self.op1 = o1;
-                self.op2 = o2;
-                self.addr = addr;
-                self.operation = operation;
-                self.rob_entry = rob_entry;
-                self.set_cycles();
-            }  else {
-                self.op1_next = o1;
-                self.op2_next = o2;
-                self.addr_next = addr;
-                self.operation_next = operation;
-                self.rob_entry_next = rob_entry;
-            }
-        }
-        return correct_type;
-    }
-
-    fn set_cycles(&mut self) {
-        match self.fu_type {
-            FUType::ALU => {
-                match self.operation {
-                    Op::Add => {
-                        self.cycles = 1;
-                    },
-                    Op::And => {
-                        self.cycles = 1;
-                    },
-                    Op::Or => {
-                        self.cycles = 1;
-                    },
-                    Op::Sub => {
-                        self.cycles = 1;
-                    }
-                    Op::Xor => {
-                        self.cycles = 1;
-                    }
-                    Op::Mov => {
-                        self.cycles = 1;
-                    }
-                    Op::Sl => {
-                        self.cycles = 1;
-                    }
-                    Op::Sr => {
-                        self.cycles = 1;
-                    }
-                    _ => (),
-                }
-            },
-            FUType::Multiplier => {
-                match self.operation {
-                    Op::Mult => {
-                        self.cycles = 2;
-                    },
-                    Op::Div => {
-                        self.cycles = 3;
-                    },
-                    Op::Mod => {
-                        self.cycles = 3;
-                    }
-                        _ => (),
-                    }
-            },
-            FUType::Branch => {
-                match self.operation {
-                    Op::Beq => {
-                        self.cycles = 1;
-                    },
-                    Op::Beqz => {
-                        self.cycles = 1;
-                    },
-                    Op::Blt => {
-                        self.cycles = 1;
-                    },
-                    Op::Bgt => {
-                        self.cycles = 1;
-                    }
-                    _ => (),
-                }
-            },
+        self.issue_wait = match self.pipelining {
+            Pipelining::Pipelined => 1,
+            Pipelining::Unpipelined => latency,
+            Pipelining::Interval(interval) => interval,
         };
+        self.in_flight.push(FUOperation {
+            op1: o1,
+            op2: o2,
+            addr,
+            operation,
+            rob_entry,
+            cycles: latency,
+        });
+        self.operations += 1;
+        true
     }
 
     fn cycle(&mut self) {
-        if self.cycles > 0 {
-            self.cycles -= 1;
-            if self.cycles == 0 {
-                self.result = match self.fu_type {
-                    FUType::ALU => {
-                        match self.operation {
-                            Op::Add => {
-                                Some((self.rob_entry,ExecResult::Value(self.op1 + self.op2)))
-                            },
-                            Op::And => {
-                                Some((self.rob_entry,ExecResult::Value(self.op1 & self.op2)))
-                            },
-                            Op::Or => {
-                                Some((self.rob_entry,ExecResult::Value(self.op1 | self.op2)))
-                            },
-                            Op::Sub => {
-                                Some((self.rob_entry,ExecResult::Value(self.op1 - self.op2)))
-                            },
-                            Op::Xor => {
-                                Some((self.rob_entry,ExecResult::Value(self.op1 ^ self.op2)))
-                            },
-                            Op::Mov => {
-                                Some((self.rob_entry,ExecResult::Value(self.op1)))
-                            }
-                            Op::Sr => {
-                                Some((self.rob_entry,ExecResult::Value(self.op1 >> self.op2)))
-                            }
-                            Op::Sl => {
-                                Some((self.rob_entry,ExecResult::Value(self.op1 << self.op2)))
-                            }
-                            _ => {
-                                panic!("Not an ALU operation {:?}", self.operation);
-                            },
-                        }
-                    },
-                    FUType::Multiplier => {
-                        match self.operation {
-                            Op::Div => {
-                                if self.op2 == 0{
-                                    Some((self.rob_entry,ExecResult::Value(0)))
-                                } else {
-                                    Some((self.rob_entry,ExecResult::Value(self.op1 / self.op2)))
-                                }
-                            },
-                            Op::Mult => {
-                                Some((self.rob_entry,ExecResult::Value(self.op1 * self.op2)))
-                            },
-                            Op::Mod => {
-                                if self.op2 == 0 {
-                                    Some((self.rob_entry,ExecResult::Value(0)))
-                                } else {
-                                    Some((self.rob_entry,ExecResult::Value(self.op1 % self.op2)))
-                                }
-                            }
-                            _ => {
-                                panic!("Not a MULTIPLIER operation {:?}", self.operation);
-                            },
-                        }
-                    },
-                    FUType::Branch => {
-                        match self.operation {
-                            Op::Beq => {
-                                if self.op1 == self.op2 {
-                                    Some((self.rob_entry, ExecResult::BranchTaken(self.addr)))
-                                } else {
-                                    Some((self.rob_entry, ExecResult::BranchNotTaken()))
-                                }
-                                
-                            },
-                            Op::Beqz => {
-                                if self.op1 == 0 {
-                                    Some((self.rob_entry, ExecResult::BranchTaken(self.addr)))
-                                } else {
-                                    Some((self.rob_entry, ExecResult::BranchNotTaken())) 
-                                }
-                                
-                            },
-                            Op::Blt => {
-                                if self.op1 < self.op2 {
-                                    Some((self.rob_entry, ExecResult::BranchTaken(self.addr)))
-                                } else {
-                                    Some((self.rob_entry, ExecResult::BranchNotTaken()))
-                                }
-                                
-                            },
-                            Op::Bgt => {
-                                if self.op1 > self.op2 {
-                                    Some((self.rob_entry, ExecResult::BranchTaken(self.addr)))
-                                } else {
-                                    Some((self.rob_entry, ExecResult::BranchNotTaken()))
-                                }
-                                
-                            },
-                            _ => {
-                               panic!("Not a BRANCH operation {:?}", self.operation); 
-                            }
-                        }
-                    },
-                };
-                
-                match self.operation_next {
-                    Op::None => (),
-                    _ => {
-                        self.operation = self.operation_next;
-                        self.op1 = self.op1_next;
-                        self.op2 = self.op2_next;
-                        self.addr = self.addr_next;
-                        self.rob_entry = self.rob_entry_next;
-                        self.set_cycles();
-                        self.operation_next = Op::None;
-                    }
-                };
+        if !self.results.is_empty() {
+            self.busy_cycles += 1;
+            self.writeback_stall_cycles += 1;
+            return;
+        }
+        if !self.in_flight.is_empty() {
+            self.busy_cycles += 1;
+        }
+        if self.issue_wait > 0 {
+            self.issue_wait -= 1;
+        }
+        for op in &mut self.in_flight {
+            op.cycles -= 1;
+            if op.cycles == 0 {
+                self.results.push_back((op.rob_entry, compute(op.operation, op.op1, op.op2, op.addr)));
             }
         }
-        
+        self.in_flight.retain(|op| op.cycles > 0);
     }
 
     fn finished(&self) -> bool {
-        if let None = self.result {
-            if self.cycles == 0  {
-                true
-            }
-            else {
-                false
-            }
-        }
-        else {
-            false
+        self.in_flight.is_empty() && self.results.is_empty()
+    }
+
+    // What the unit is doing for the timeline, holding a result that lost arbitration freezing it
+    fn activity(&self) -> Option<String> {
+        if !self.results.is_empty() {
+            Some(String::from("Writeback stall"))
+        } else if !self.in_flight.is_empty() {
+            let operations: Vec<String> = self.in_flight.iter().map(|op| format!("{:?}", op.operation)).collect();
+            Some(operations.join(" "))
+        } else {
+            None
         }
     }
 
+    // The ROB entry of the next result and whether it writes a register
+    fn peek_result(&self) -> Option<(usize, bool)> {
+        self.results.front().map(|&(rob_entry, result)| {
+            match result {
+                ExecResult::Value(_) => (rob_entry, true),
+                _ => (rob_entry, false),
+            }
+        })
+    }
+
     fn get_result(&mut self) -> Option<(ExecResult, usize)> {
-        if let Some((r, x)) = self.result {
-            self.result = None;
-            Some((x, r))
-        }
-        else {
-            None
+        self.results.pop_front().map(|(r, x)| (x, r))
+    }
+
+    fn reset(&mut self, thread: usize) {
+        self.in_flight.retain(|op| ReorderBuffer::thread_of(op.rob_entry) != thread);
+        self.results = self.results.iter().cloned().filter(|&(rob_entry, _)| ReorderBuffer::thread_of(rob_entry) != thread).collect();
+        if self.in_flight.is_empty() {
+            self.issue_wait = 0;
         }
     }
+}
 
-    fn reset(&mut self) {
-        self.op1 = 0;
-        self.op2 = 0;
-        self.operation = Op::None;
-        self.addr = 0;
-        self.operation_next = Op::None;
-        self.cycles = 0;
-        self.result = None;
-        self.rob_entry = 0; 
+fn compute(operation: Op, op1: u32, op2: u32, addr: usize) -> ExecResult {
+    match operation {
+        //Registers are 32 bit words so arithmetic wraps, which also keeps wrong path operands harmless
+        Op::Add => ExecResult::Value(op1.wrapping_add(op2)),
+        Op::And => ExecResult::Value(op1 & op2),
+        Op::Or => ExecResult::Value(op1 | op2),
+        Op::Sub => ExecResult::Value(op1.wrapping_sub(op2)),
+        Op::Xor => ExecResult::Value(op1 ^ op2),
+        Op::Mov => ExecResult::Value(op1),
+        Op::Sr => ExecResult::Value(op1.wrapping_shr(op2)),
+        Op::Sl => ExecResult::Value(op1.wrapping_shl(op2)),
+        Op::Mult => ExecResult::Value(op1.wrapping_mul(op2)),
+        Op::Div => {
+            if op2 == 0 {
+                ExecResult::Value(0)
+            } else {
+                ExecResult::Value(op1 / op2)
+            }
+        },
+        Op::Mod => {
+            if op2 == 0 {
+                ExecResult::Value(0)
+            } else {
+                ExecResult::Value(op1 % op2)
+            }
+        },
+        Op::Beq => if op1 == op2 { ExecResult::BranchTaken(addr) } else { ExecResult::BranchNotTaken() },
+        Op::Beqz => if op1 == 0 { ExecResult::BranchTaken(addr) } else { ExecResult::BranchNotTaken() },
+        Op::Blt => if op1 < op2 { ExecResult::BranchTaken(addr) } else { ExecResult::BranchNotTaken() },
+        Op::Bgt => if op1 > op2 { ExecResult::BranchTaken(addr) } else { ExecResult::BranchNotTaken() },
+        Op::None => panic!("Executed an empty operation"),
     }
 }
 
 #[derive(Debug, Copy, Clone)]
 enum Operand {
     Value(u32),
-    Rob(usize),
+    // The ROB entry or physical register that will produce the value
+    Tag(usize),
     None,
 }
 
 #[derive(Debug)]
 struct ReservationStation {
+    // Cycles left in the dispatch stages before the station can be selected
+    wait: u32,
     rob_entry: usize,
     o1: Operand,
     o2: Operand,
@@ -1295,6 +3825,7 @@ struct ReservationStation {
 impl ReservationStation {
     fn new() -> ReservationStation {
         ReservationStation {
+            wait: 0,
             rob_entry: 0,
             o1: Operand::None,
             o2: Operand::None,
@@ -1358,12 +3889,12 @@ impl ReservationStation {
     }
 
     fn resolve_dependency(&mut self, x: u32, rob_entry: usize) {
-        if let Operand::Rob(r) = self.o1 {
+        if let Operand::Tag(r) = self.o1 {
             if rob_entry == r {
                 self.o1 = Operand::Value(x);
             }
         }
-        if let Operand::Rob(r) = self.o2 {
+        if let Operand::Tag(r) = self.o2 {
             if rob_entry == r {
                 self.o2 = Operand::Value(x);
             }
@@ -1374,12 +3905,12 @@ impl ReservationStation {
 
     fn dependencies_resolved(&self) -> bool {
         match self.o1 {
-            Operand::Rob(_) => {
+            Operand::Tag(_) => {
                 false
             },
             _ => {
                 match self.o2 {
-                    Operand::Rob(_) => {
+                    Operand::Tag(_) => {
                         false
                     }
                     _ => {
@@ -1397,7 +3928,6 @@ impl ReservationStation {
 
 struct BranchPredictor {
     bht: [ u32 ; MAX_PREDICTIONS],
-    btb: [ (usize, bool) ; MAX_PREDICTIONS],
     total_predictions: u32,
     total_correct: u32,
     pred_type: usize,
@@ -1414,12 +3944,16 @@ impl BranchPredictor {
         };
         BranchPredictor {
             bht: bht,
-            btb: [ (0, false) ; MAX_PREDICTIONS],
             total_predictions: 0,
             total_correct: 0,
             pred_type: pred_type
         }
     }
+
+    fn record(&self, stats: &mut Stats) {
+        stats.counter("predictions", self.total_predictions as u64);
+        stats.counter("correct", self.total_correct as u64);
+    }
     fn accuracy(&self) -> f32 {
         self.total_correct as f32 / self.total_predictions as f32
     }
@@ -1428,7 +3962,6 @@ impl BranchPredictor {
         let index = pc & 0b1111111111; //Use least significant 10 bits for index
         if self.pred_type == 0 {
             //static prediction
-            self.btb[index] = (pc + 1, false);
             pc + 1
         } else { 
             match instruction {
@@ -1451,28 +3984,31 @@ impl BranchPredictor {
         }
     }
 
+    //Peeks at the prediction for a branch without updating the tables
+    fn predicts_taken(&self, pc: usize) -> bool {
+        let index = pc & 0b1111111111;
+        self.pred_type != 0 && self.bht[index] > ((1 << self.pred_type) - 1) / 2
+    }
+
     fn make_prediction(&mut self, entry: usize, inst: usize, pc: usize) -> usize {
         if self.pred_type == 0 {
-            self.btb[entry] = (pc + 1, false);
             pc + 1
         } else {
             if self.bht[entry] <= ((1 << self.pred_type) - 1) / 2 {
-                self.btb[entry] = (pc + 1, false);
                 pc + 1
             } else {
-                self.btb[entry] = (inst, true);
                 inst
             }
         }
     }
 
-    fn prediction_correct(&mut self, taken_pc: usize, pc: usize) -> bool {
-        //TODO Need to remove from table also
+    //The prediction travels with the branch, as several instances of one branch can be in flight
+    fn prediction_correct(&mut self, predicted_pc: usize, taken_pc: usize, pc: usize) -> bool {
         self.total_predictions += 1;
 
         let entry = pc & 0b1111111111;
 
-        let (predicted_pc, predicted_branch_taken) = self.btb[entry];
+        let predicted_branch_taken = predicted_pc != pc + 1;
 
         if predicted_pc == taken_pc {
             if self.pred_type >= 1 {
@@ -1516,8 +4052,8 @@ enum ExecResult {
 #[derive(Debug, Copy, Clone)]
 enum ReorderBufferResult {
     Writeback(u32, usize, usize),
-    BranchTaken(usize, usize),
-    BranchNotTaken(usize),
+    BranchTaken(usize, usize, usize),
+    BranchNotTaken(usize, usize),
     Store(usize),
     None,
 }
@@ -1526,6 +4062,14 @@ enum ReorderBufferResult {
 struct ReorderBufferEntry {
     register: usize,
     result: Option<ExecResult>,
+    // The physical register allocated to the destination and the one it replaced
+    rename: Option<(usize, usize)>,
+    checkpoint: Option<usize>,
+    // Where fetch was sent after a branch
+    predicted: usize,
+    pc: usize,
+    // Cycle the instruction left its reservation station or the LSQ, stores only leaving once committed
+    issued: Option<u64>,
 }
 
 impl ReorderBufferEntry {
@@ -1533,6 +4077,11 @@ impl ReorderBufferEntry {
         ReorderBufferEntry {
             register: 0,
             result: None,
+            rename: None,
+            checkpoint: None,
+            predicted: 0,
+            pc: 0,
+            issued: None,
         }
     }
 
@@ -1541,61 +4090,137 @@ impl ReorderBufferEntry {
     }
 }
 
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum RobSharing {
+    Shared,
+    Partitioned,
+}
+
+impl RobSharing {
+    fn parse(name: &str) -> RobSharing {
+        match name.to_lowercase().as_str() {
+            "shared" => RobSharing::Shared,
+            "partitioned" => RobSharing::Partitioned,
+            _ => panic!("Unaccepted ROB sharing {}", name),
+        }
+    }
+}
+
+// Each thread has its own circular region of ROB_SIZE entries so ROB entries name a thread,
+// while the entries actually in use are limited to those of the single threaded ROB
 #[derive(Debug)]
 struct ReorderBuffer {
     instructions_committed: usize,
-    commit: usize,
-    issue: usize,
-    buffer: [ReorderBufferEntry; ROB_SIZE],
+    thread_committed: Vec<usize>,
+    commit: Vec<usize>,
+    issue: Vec<usize>,
+    buffer: Vec<ReorderBufferEntry>,
+    // Entries one thread may hold
+    limit: usize,
+    commit_width: usize,
+    // Cycles where the head of a non empty ROB had not finished
+    stall_cycles: u64,
+    empty_cycles: u64,
+    // Cycles the ROB held each number of entries
+    occupancy: Vec<u64>,
 }
 
 impl ReorderBuffer {
-    fn new() -> ReorderBuffer {
+    fn new(commit_width: usize, threads: usize, sharing: RobSharing) -> ReorderBuffer {
+        if commit_width == 0 {
+            panic!("Commit width must be non zero");
+        }
+        // One entry of the circular buffer is always left free to tell full from empty
+        let limit = match sharing {
+            RobSharing::Shared => ROB_SIZE - 1,
+            RobSharing::Partitioned => (ROB_SIZE - 1) / threads,
+        };
+        if limit == 0 {
+            panic!("The ROB is too small to partition between {} threads", threads);
+        }
         ReorderBuffer {
             instructions_committed: 0,
-            commit: 0,
-            issue: 0,
-            buffer: [ReorderBufferEntry::new() ; ROB_SIZE],
+            thread_committed: vec![0; threads],
+            commit: (0..threads).map(|t| t * ROB_SIZE).collect(),
+            issue: (0..threads).map(|t| t * ROB_SIZE).collect(),
+            buffer: vec![ReorderBufferEntry::new() ; ROB_SIZE * threads],
+            limit,
+            commit_width,
+            stall_cycles: 0,
+            empty_cycles: 0,
+            occupancy: vec![0; ROB_SIZE],
         }
     }
 
+    fn record(&self, stats: &mut Stats) {
+        stats.counter("stall_cycles", self.stall_cycles);
+        stats.counter("empty_cycles", self.empty_cycles);
+        stats.histogram("occupancy", &self.occupancy);
+    }
+
+    fn thread_of(rob_entry: usize) -> usize {
+        rob_entry / ROB_SIZE
+    }
+
     fn is_empty(&self) -> bool {
-        self.commit == self.issue
+        (0..self.commit.len()).all(|t| self.thread_empty(t))
+    }
+
+    fn thread_empty(&self, thread: usize) -> bool {
+        self.commit[thread] == self.issue[thread]
+    }
+
+    fn len(&self, thread: usize) -> usize {
+        (self.issue[thread] + ROB_SIZE - self.commit[thread]) % ROB_SIZE
+    }
+
+    // Position of an entry counting from the head of its thread's region
+    fn age(&self, rob_entry: usize) -> usize {
+        let head = self.commit[ReorderBuffer::thread_of(rob_entry)];
+        (rob_entry + ROB_SIZE - head) % ROB_SIZE
     }
 
-    fn empty(&mut self) {
-        for entry in &mut self.buffer {
+    fn is_full(&self, thread: usize) -> bool {
+        let used: usize = (0..self.commit.len()).map(|t| self.len(t)).sum();
+        self.len(thread) == self.limit || used == ROB_SIZE - 1
+    }
+
+    fn empty(&mut self, thread: usize) {
+        let base = thread * ROB_SIZE;
+        for entry in &mut self.buffer[base..base + ROB_SIZE] {
             entry.clear();
         }
-        self.commit = 0;
-        self.issue = 0;
-        self.issue = self.commit;
+        self.commit[thread] = base;
+        self.issue[thread] = base;
     }
 
-    fn commit_to_store(&mut self,  register: usize) -> Option<usize> {
-        if self.inc(self.issue) == self.commit {
-            None
-        } else {
-            let ret = self.issue;
+    fn commit_to_store(&mut self, thread: usize, register: usize) -> Option<usize> {
+        let ret = self.commit_to(thread, register);
+        if let Some(ret) = ret {
             self.buffer[ret].result = Some(ExecResult::Store);
-            self.buffer[ret].register = register;
-            self.issue = (self.issue + 1) % self.buffer.len();
-            Some(ret)
         }
+        ret
     }
 
     fn inc(&self, x: usize) -> usize {
-        (x + 1) % self.buffer.len()
+        x - x % ROB_SIZE + (x + 1) % ROB_SIZE
+    }
+
+    fn dec(&self, x: usize) -> usize {
+        x - x % ROB_SIZE + (x + ROB_SIZE - 1) % ROB_SIZE
     }
 
-    fn commit_to(&mut self,  register: usize) -> Option<usize> {
-        if self.inc(self.issue) == self.commit {
+    fn commit_to(&mut self, thread: usize, register: usize) -> Option<usize> {
+        if self.is_full(thread) {
             None
         } else {
-            let ret = self.issue;
+            let ret = self.issue[thread];
             self.buffer[ret].result = None;
             self.buffer[ret].register = register;
-            self.issue = self.inc(self.issue);
+            self.buffer[ret].rename = None;
+            self.buffer[ret].checkpoint = None;
+            self.buffer[ret].issued = None;
+            self.issue[thread] = self.inc(ret);
             Some(ret)
         }
     }
@@ -1604,25 +4229,29 @@ impl ReorderBuffer {
         self.buffer[pos].result = Some((result));
     }
 
-    fn get_commit(&mut self) -> ReorderBufferResult {
-        if let Some(result) = self.buffer[self.commit].result {
+    fn get_commit(&mut self, thread: usize) -> ReorderBufferResult {
+        let head = self.commit[thread];
+        if head == self.issue[thread] {
+            return ReorderBufferResult::None;
+        }
+        if let Some(result) = self.buffer[head].result {
             self.instructions_committed += 1;
-            let rob_ret = self.commit;
-            let reg_ret = self.buffer[self.commit].register;
-            self.buffer[self.commit].clear();
-            self.commit = (self.commit + 1) % self.buffer.len();
+            self.thread_committed[thread] += 1;
+            let reg_ret = self.buffer[head].register;
+            self.buffer[head].clear();
+            self.commit[thread] = self.inc(head);
             match result {
                 ExecResult::Value(val) => {
-                    ReorderBufferResult::Writeback(val, rob_ret, reg_ret)
+                    ReorderBufferResult::Writeback(val, head, reg_ret)
                 }
                 ExecResult::BranchTaken(inst) => {
-                    ReorderBufferResult::BranchTaken(inst, reg_ret)
+                    ReorderBufferResult::BranchTaken(inst, reg_ret, head)
                 }
                 ExecResult::BranchNotTaken() => {
-                    ReorderBufferResult::BranchNotTaken(reg_ret)
+                    ReorderBufferResult::BranchNotTaken(reg_ret, head)
                 }
                 ExecResult::Store => {
-                    ReorderBufferResult::Store(rob_ret)
+                    ReorderBufferResult::Store(head)
                 }
             }
         } else {
@@ -1631,30 +4260,178 @@ impl ReorderBuffer {
     }
 }
 
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum RenameScheme {
+    Rob,
+    Prf,
+}
+
+impl RenameScheme {
+    fn parse(name: &str) -> RenameScheme {
+        match name.to_lowercase().as_str() {
+            "rob" => RenameScheme::Rob,
+            "prf" => RenameScheme::Prf,
+            _ => panic!("Unaccepted rename scheme {}", name),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Checkpoint {
+    rat: [Option<usize>; 32],
+    map: [usize; 32],
+}
+
 #[derive(Debug)]
 struct Registers {
     gprs: [u32; 32], // 32 GPRS
     rat: [Option<usize>; 32], 
+    scheme: RenameScheme,
+    prf: Vec<u32>,
+    prf_ready: Vec<bool>,
+    map: [usize; 32],
+    retirement_map: [usize; 32],
+    free_list: LinkedList<usize>,
+    free_list_stalls: u64,
+    checkpoints: Vec<Option<Checkpoint>>,
+    // Cycles decode stalled on a branch with every checkpoint taken, counted once however often it retries
+    checkpoint_stalls: u64,
+    checkpoint_stalled: bool,
 }
 
 impl Registers {
-    fn new() -> Registers {
-        Registers{
+    fn new(scheme: RenameScheme, prf_size: usize, checkpoints: usize) -> Registers {
+        if checkpoints == 0 {
+            panic!("At least one rename map checkpoint is needed");
+        }
+        if scheme == RenameScheme::Prf && prf_size <= 32 {
+            panic!("The physical register file needs more than 32 registers, got {}", prf_size);
+        }
+        let mut map = [0; 32];
+        for (reg, preg) in map.iter_mut().enumerate() {
+            *preg = reg;
+        }
+        let mut registers = Registers{
             gprs: [0u32; 32],
             rat: [None; 32],
+            scheme,
+            prf: vec![0; prf_size],
+            prf_ready: vec![true; prf_size],
+            map,
+            retirement_map: map,
+            free_list: LinkedList::new(),
+            free_list_stalls: 0,
+            checkpoints: vec![None; checkpoints],
+            checkpoint_stalls: 0,
+            checkpoint_stalled: false,
+        };
+        registers.rebuild_free_list();
+        registers
+    }
+
+    fn record(&self, stats: &mut Stats) {
+        stats.counter("free_list_stalls", self.free_list_stalls);
+        stats.counter("checkpoint_stalls", self.checkpoint_stalls);
+    }
+
+    fn rebuild_free_list(&mut self) {
+        self.free_list.clear();
+        if self.scheme == RenameScheme::Rob {
+            return;
+        }
+        for preg in 0..self.prf.len() {
+            if self.retirement_map.contains(&preg) {
+                self.prf_ready[preg] = true;
+            } else {
+                self.free_list.push_back(preg);
+            }
         }
     }
 
-    fn clear_rat(&mut self) {
-        for i in 0..self.rat.len() {
-            self.rat[i] = None;
+    fn new_cycle(&mut self) {
+        self.checkpoint_stalled = false;
+    }
+
+    fn can_checkpoint(&mut self) -> bool {
+        if self.checkpoints.iter().any(|c| c.is_none()) {
+            true
+        } else {
+            if !self.checkpoint_stalled {
+                self.checkpoint_stalled = true;
+                self.checkpoint_stalls += 1;
+            }
+            false
         }
     }
 
+    fn take_checkpoint(&mut self) -> usize {
+        let index = self.checkpoints.iter().position(|c| c.is_none()).expect("Checkpointed without a free checkpoint");
+        self.checkpoints[index] = Some(Checkpoint { rat: self.rat, map: self.map });
+        index
+    }
+
+    fn release_checkpoint(&mut self, index: usize) {
+        self.checkpoints[index] = None;
+    }
+
+    fn restore_checkpoint(&mut self, index: usize) {
+        let checkpoint = self.checkpoints[index].take().expect("Restored a free checkpoint");
+        self.rat = checkpoint.rat;
+        self.map = checkpoint.map;
+    }
+
     fn set_owner(&mut self, reg: usize, new_owner: usize) {
         self.rat[reg] = Some(new_owner);
     }
 
+    fn can_rename(&mut self) -> bool {
+        if self.scheme == RenameScheme::Prf && self.free_list.is_empty() {
+            self.free_list_stalls += 1;
+            false
+        } else {
+            true
+        }
+    }
+
+    fn rename(&mut self, reg: usize, rob_entry: usize) -> Option<(usize, usize)> {
+        match self.scheme {
+            RenameScheme::Rob => {
+                self.set_owner(reg, rob_entry);
+                None
+            },
+            RenameScheme::Prf => {
+                let preg = self.free_list.pop_front().expect("Renamed without a free physical register");
+                let previous = self.map[reg];
+                self.map[reg] = preg;
+                self.prf_ready[preg] = false;
+                Some((preg, previous))
+            },
+        }
+    }
+
+    // Gives an architectural register a starting value
+    fn set(&mut self, reg: usize, value: u32) {
+        self.gprs[reg] = value;
+        let preg = self.retirement_map[reg];
+        self.prf[preg] = value;
+    }
+
+    fn write_physical(&mut self, preg: usize, value: u32) {
+        self.prf[preg] = value;
+        self.prf_ready[preg] = true;
+    }
+
+    fn retire(&mut self, value: u32, rob: usize, register: usize, rename: Option<(usize, usize)>) {
+        match rename {
+            Some((preg, previous)) => {
+                self.gprs[register] = value;
+                self.retirement_map[register] = preg;
+                self.free_list.push_back(previous);
+            },
+            None => self.write_result(value, rob, register),
+        }
+    }
+
     fn write_result(&mut self, value: u32, rob: usize, register: usize) {
         self.gprs[register] = value;
         if let Some(rat_entry) = self.rat[register] {
@@ -1662,6 +4439,14 @@ impl Registers {
                 self.rat[register] = None;
             }
         }
+        //Checkpoints must not point at the entry once it leaves the ROB either
+        for checkpoint in self.checkpoints.iter_mut() {
+            if let Some(ref mut c) = *checkpoint {
+                if c.rat[register] == Some(rob) {
+                    c.rat[register] = None;
+                }
+            }
+        }
     }
 }
 
@@ -1678,8 +4463,10 @@ enum EncodedInstruction {
     Bgt(usize, usize, usize),
     Blt(usize, usize, usize),
     Div(usize, usize, usize),
+    Fence,
     J(usize),
     Ldc(usize, u32),
+    Ll(usize, usize),
     Lw(usize, usize),
     Mod(usize, usize, usize),
     Mov(usize, usize),
@@ -1687,12 +4474,106 @@ enum EncodedInstruction {
     Or(usize, usize, usize),
     Sl(usize, usize, u32),
     Sr(usize, usize, u32),
+    Sc(usize, usize, usize),
     Sw(usize, usize),
     Sub(usize, usize, usize),
     Subi(usize, usize, u32),
     Xor(usize, usize, usize),
 }
 
+// Instructions that allocate a new mapping for a destination register at rename
+fn writes_register(instruction: EncodedInstruction) -> bool {
+    !matches!(instruction,
+        EncodedInstruction::Noop |
+        EncodedInstruction::Halt |
+        EncodedInstruction::Beq(_, _, _) |
+        EncodedInstruction::Beqz(_, _) |
+        EncodedInstruction::Bgt(_, _, _) |
+        EncodedInstruction::Blt(_, _, _) |
+        EncodedInstruction::Fence |
+        EncodedInstruction::J(_) |
+        EncodedInstruction::Sw(_, _))
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum InstructionKind {
+    Alu,
+    Load,
+    Store,
+    Branch,
+}
+
+#[derive(Debug, Copy, Clone)]
+enum Source {
+    Reg(usize),
+    Imm(u32),
+}
+
+// An instruction broken down for the cores that read operands straight from the register file
+#[derive(Debug, Copy, Clone)]
+struct DecodedInstruction {
+    kind: InstructionKind,
+    op: Op,
+    dest: Option<usize>,
+    // The first source is the address for memory operations and the second the value to store
+    sources: [Source; 2],
+    target: usize,
+}
+
+impl DecodedInstruction {
+    // Jumps and no-ops only change the instruction stream so have nothing to execute
+    fn new(instruction: EncodedInstruction) -> Option<DecodedInstruction> {
+        let (kind, op, dest, sources, target) = match instruction {
+            EncodedInstruction::Add(d, s, t) => (InstructionKind::Alu, Op::Add, Some(d), [Source::Reg(s), Source::Reg(t)], 0),
+            EncodedInstruction::Addi(d, s, imm) => (InstructionKind::Alu, Op::Add, Some(d), [Source::Reg(s), Source::Imm(imm)], 0),
+            EncodedInstruction::And(d, s, t) => (InstructionKind::Alu, Op::And, Some(d), [Source::Reg(s), Source::Reg(t)], 0),
+            EncodedInstruction::Andi(d, s, imm) => (InstructionKind::Alu, Op::And, Some(d), [Source::Reg(s), Source::Imm(imm)], 0),
+            EncodedInstruction::Beq(s, t, inst) => (InstructionKind::Branch, Op::Beq, None, [Source::Reg(s), Source::Reg(t)], inst),
+            EncodedInstruction::Beqz(s, inst) => (InstructionKind::Branch, Op::Beqz, None, [Source::Reg(s), Source::Imm(0)], inst),
+            EncodedInstruction::Bgt(s, t, inst) => (InstructionKind::Branch, Op::Bgt, None, [Source::Reg(s), Source::Reg(t)], inst),
+            EncodedInstruction::Blt(s, t, inst) => (InstructionKind::Branch, Op::Blt, None, [Source::Reg(s), Source::Reg(t)], inst),
+            EncodedInstruction::Div(d, s, t) => (InstructionKind::Alu, Op::Div, Some(d), [Source::Reg(s), Source::Reg(t)], 0),
+            EncodedInstruction::Ldc(d, imm) => (InstructionKind::Alu, Op::Mov, Some(d), [Source::Imm(imm), Source::Imm(0)], 0),
+            EncodedInstruction::Lw(addr, dest) => (InstructionKind::Load, Op::None, Some(dest), [Source::Reg(addr), Source::Imm(0)], 0),
+            EncodedInstruction::Mod(d, s, t) => (InstructionKind::Alu, Op::Mod, Some(d), [Source::Reg(s), Source::Reg(t)], 0),
+            EncodedInstruction::Mov(d, s) => (InstructionKind::Alu, Op::Mov, Some(d), [Source::Reg(s), Source::Imm(0)], 0),
+            EncodedInstruction::Mult(d, s, t) => (InstructionKind::Alu, Op::Mult, Some(d), [Source::Reg(s), Source::Reg(t)], 0),
+            EncodedInstruction::Or(d, s, t) => (InstructionKind::Alu, Op::Or, Some(d), [Source::Reg(s), Source::Reg(t)], 0),
+            EncodedInstruction::Sl(d, s, imm) => (InstructionKind::Alu, Op::Sl, Some(d), [Source::Reg(s), Source::Imm(imm)], 0),
+            EncodedInstruction::Sr(d, s, imm) => (InstructionKind::Alu, Op::Sr, Some(d), [Source::Reg(s), Source::Imm(imm)], 0),
+            EncodedInstruction::Sw(addr, val) => (InstructionKind::Store, Op::None, None, [Source::Reg(addr), Source::Reg(val)], 0),
+            EncodedInstruction::Sub(d, s, t) => (InstructionKind::Alu, Op::Sub, Some(d), [Source::Reg(s), Source::Reg(t)], 0),
+            EncodedInstruction::Subi(d, s, imm) => (InstructionKind::Alu, Op::Sub, Some(d), [Source::Reg(s), Source::Imm(imm)], 0),
+            EncodedInstruction::Xor(d, s, t) => (InstructionKind::Alu, Op::Xor, Some(d), [Source::Reg(s), Source::Reg(t)], 0),
+            //Memory operations already complete in program order so fences have nothing to wait for
+            EncodedInstruction::Noop |
+            EncodedInstruction::Halt |
+            EncodedInstruction::Fence |
+            EncodedInstruction::J(_) => return None,
+            EncodedInstruction::Ll(_, _) |
+            EncodedInstruction::Sc(_, _, _) => panic!("Load linked and store conditional need the out of order core or the interpreter"),
+        };
+        Some(DecodedInstruction {
+            kind,
+            op,
+            dest,
+            sources,
+            target,
+        })
+    }
+
+    fn reads(&self, reg: usize) -> bool {
+        self.sources.iter().any(|source| match *source {
+            Source::Reg(r) => r == reg,
+            Source::Imm(_) => false,
+        })
+    }
+
+    fn is_memory(&self) -> bool {
+        self.kind == InstructionKind::Load || self.kind == InstructionKind::Store
+    }
+}
+
 fn assemble(assembly: Vec<String>) -> Vec<EncodedInstruction> {
     let mut instructions: Vec<EncodedInstruction> = Vec::new();
 
@@ -1735,6 +4616,9 @@ fn assemble(assembly: Vec<String>) -> Vec<EncodedInstruction> {
                 let (d, s, t) = three_args(split_inst);
                 instructions.push(EncodedInstruction::Div(d, s, t));
             }
+            "FENCE" => {
+                instructions.push(EncodedInstruction::Fence);
+            }
             "J" => {
                 let imm = split_inst[1].parse::<usize>().unwrap();
                 instructions.push(EncodedInstruction::J(imm));
@@ -1743,6 +4627,10 @@ fn assemble(assembly: Vec<String>) -> Vec<EncodedInstruction> {
                 let (s, imm) = two_args(split_inst);
                 instructions.push(EncodedInstruction::Ldc(s, imm as u32));
             }
+            "LL" => {
+                let (s, t) = two_args(split_inst);
+                instructions.push(EncodedInstruction::Ll(s, t));
+            }
             "LW" => { // LW
                 let (s, t) = two_args(split_inst);
                 instructions.push(EncodedInstruction::Lw(s, t));
@@ -1766,6 +4654,10 @@ fn assemble(assembly: Vec<String>) -> Vec<EncodedInstruction> {
                 let (d, s, t) = three_args(split_inst);
                 instructions.push(EncodedInstruction::Or(d, s, t));
             }
+            "SC" => {
+                let (addr, val, d) = three_args(split_inst);
+                instructions.push(EncodedInstruction::Sc(addr, val, d));
+            }
             "SL" => {
                 let (d, s, t) = three_args(split_inst);
                 instructions.push(EncodedInstruction::Sl(d, s, t as u32));
@@ -0,0 +1,168 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+// The steps of an instruction's trip through the out of order core, in the order it takes them
+#[derive(Debug, Copy, Clone, PartialEq, PartialOrd)]
+pub enum Stage {
+    Fetch,
+    // In the instruction queue waiting to be decoded and renamed
+    Decode,
+    // Renamed into the ROB and waiting in a reservation station or the load store queue
+    Dispatch,
+    Issue,
+    // Result computed and waiting for a result bus
+    Complete,
+    // Result in the ROB waiting to commit
+    Writeback,
+}
+
+impl Stage {
+    fn name(&self) -> &'static str {
+        match *self {
+            Stage::Fetch => "F",
+            Stage::Decode => "Dc",
+            Stage::Dispatch => "Ds",
+            Stage::Issue => "Is",
+            Stage::Complete => "Cp",
+            Stage::Writeback => "Wb",
+        }
+    }
+}
+
+// Writes each dynamic instruction's stages in the Kanata log format read by the Konata pipeline viewer.
+// Instructions are known by their thread and fetch sequence number in the front end and by their
+// ROB entry once renamed, with every instruction still in flight mapped to its id in the log
+pub struct PipelineTrace {
+    out: BufWriter<File>,
+    pub cycle: u64,
+    // The cycle the log has been advanced to
+    written: u64,
+    next_id: u64,
+    retired: u64,
+    front_end: Vec<HashMap<u64, (u64, Stage)>>,
+    rob: HashMap<usize, (u64, Stage)>,
+    // Jumps and no-ops each thread decoded, by the ROB entry they retire after
+    consumed: Vec<Vec<(usize, u64)>>,
+}
+
+impl PipelineTrace {
+    pub fn new(path: &str, threads: usize) -> PipelineTrace {
+        let mut out = BufWriter::new(File::create(path).unwrap());
+        writeln!(out, "Kanata\t0004\nC=\t0").unwrap();
+        PipelineTrace {
+            out,
+            cycle: 0,
+            written: 0,
+            next_id: 0,
+            retired: 0,
+            front_end: vec![HashMap::new(); threads],
+            rob: HashMap::new(),
+            consumed: vec![Vec::new(); threads],
+        }
+    }
+
+    fn line(&mut self, line: String) {
+        if self.cycle != self.written {
+            writeln!(self.out, "C\t{}", self.cycle - self.written).unwrap();
+            self.written = self.cycle;
+        }
+        writeln!(self.out, "{}", line).unwrap();
+    }
+
+    fn start(&mut self, id: u64, stage: Stage) {
+        self.line(format!("S\t{}\t0\t{}", id, stage.name()));
+    }
+
+    // Ends an instruction, retired ones numbered in commit order
+    fn end(&mut self, id: u64, flushed: bool) {
+        let line = format!("R\t{}\t{}\t{}", id, self.retired, flushed as u32);
+        self.line(line);
+        if !flushed {
+            self.retired += 1;
+        }
+    }
+
+    pub fn fetch(&mut self, thread: usize, seq: u64, pc: usize, label: String) {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.line(format!("I\t{}\t{}\t{}", id, seq, thread));
+        self.line(format!("L\t{}\t0\t{}: {}", id, pc, label));
+        self.start(id, Stage::Fetch);
+        self.front_end[thread].insert(seq, (id, Stage::Fetch));
+    }
+
+    // Called every cycle for everything in the instruction queue, so only the first call counts
+    pub fn decode(&mut self, thread: usize, seq: u64) {
+        if let Some(&(id, Stage::Fetch)) = self.front_end[thread].get(&seq) {
+            self.front_end[thread].insert(seq, (id, Stage::Decode));
+            self.start(id, Stage::Decode);
+        }
+    }
+
+    pub fn dispatch(&mut self, thread: usize, seq: u64, rob_entry: usize) {
+        if let Some((id, _)) = self.front_end[thread].remove(&seq) {
+            self.rob.insert(rob_entry, (id, Stage::Dispatch));
+            self.start(id, Stage::Dispatch);
+        }
+    }
+
+    // Jumps and no-ops are done with once decoded and never take a ROB entry, so they retire once the
+    // youngest instruction ahead of them does, or straight away with none left in flight
+    pub fn consume(&mut self, thread: usize, seq: u64, behind: Option<usize>) {
+        if let Some((id, _)) = self.front_end[thread].remove(&seq) {
+            match behind {
+                Some(rob_entry) => self.consumed[thread].push((rob_entry, id)),
+                None => self.end(id, false),
+            }
+        }
+    }
+
+    // Flushes a thread's front end instructions other than those still in its fetch latches and queue
+    pub fn front_end(&mut self, thread: usize, live: &[u64]) {
+        let mut gone: Vec<(u64, u64)> = self.front_end[thread].iter().filter(|&(seq, _)| !live.contains(seq)).map(|(&seq, &(id, _))| (seq, id)).collect();
+        gone.sort_by_key(|&(_, id)| id);
+        for (seq, id) in gone {
+            self.front_end[thread].remove(&seq);
+            self.end(id, true);
+        }
+    }
+
+    // Moves a renamed instruction on to a later stage, ignoring repeats and ROB entries not traced
+    pub fn stage(&mut self, rob_entry: usize, stage: Stage) {
+        if let Some(&(id, current)) = self.rob.get(&rob_entry) {
+            if stage > current {
+                self.rob.insert(rob_entry, (id, stage));
+                self.start(id, stage);
+            }
+        }
+    }
+
+    pub fn retire(&mut self, rob_entry: usize) {
+        if let Some((id, _)) = self.rob.remove(&rob_entry) {
+            self.end(id, false);
+        }
+    }
+
+    pub fn squash(&mut self, rob_entry: usize) {
+        if let Some((id, _)) = self.rob.remove(&rob_entry) {
+            self.end(id, true);
+        }
+    }
+
+    // Retires the jumps and no-ops decoded behind a ROB entry once it has committed down the right path
+    pub fn retire_consumed(&mut self, thread: usize, rob_entry: usize) {
+        let ids: Vec<u64> = self.consumed[thread].iter().filter(|&&(entry, _)| entry == rob_entry).map(|&(_, id)| id).collect();
+        self.consumed[thread].retain(|&(entry, _)| entry != rob_entry);
+        for id in ids {
+            self.end(id, false);
+        }
+    }
+
+    pub fn squash_consumed(&mut self, thread: usize) {
+        let ids: Vec<u64> = self.consumed[thread].drain(..).map(|(_, id)| id).collect();
+        for id in ids {
+            self.end(id, true);
+        }
+    }
+}
@@ -0,0 +1,170 @@
+use std::collections::VecDeque;
+use stats::Stats;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum PrefetcherKind {
+    None,
+    NextLine,
+    Stride,
+    Stream,
+}
+
+impl PrefetcherKind {
+    pub fn parse(name: &str) -> PrefetcherKind {
+        match name.to_lowercase().as_str() {
+            "none" => PrefetcherKind::None,
+            "next-line" => PrefetcherKind::NextLine,
+            "stride" => PrefetcherKind::Stride,
+            "stream" => PrefetcherKind::Stream,
+            _ => panic!("Unaccepted prefetcher {}", name),
+        }
+    }
+}
+
+#[derive(Debug, Default, Copy, Clone)]
+pub struct PrefetchStats {
+    pub issued: u64,
+    // Prefetched lines later used by a demand access
+    pub useful: u64,
+    // Useful prefetches that had not arrived when the demand access came
+    pub late: u64,
+    // Prefetches not issued as every MSHR was busy
+    pub dropped: u64,
+}
+
+impl PrefetchStats {
+    // Each ratio is 0 rather than NaN when there was nothing to measure
+    pub fn accuracy(&self) -> f32 {
+        if self.issued == 0 { 0.0 } else { self.useful as f32 / self.issued as f32 }
+    }
+
+    pub fn coverage(&self, demand_misses: u64) -> f32 {
+        let misses = self.useful + demand_misses;
+        if misses == 0 { 0.0 } else { self.useful as f32 / misses as f32 }
+    }
+
+    pub fn timeliness(&self) -> f32 {
+        if self.useful == 0 { 0.0 } else { self.useful.saturating_sub(self.late) as f32 / self.useful as f32 }
+    }
+
+    pub fn record(&self, stats: &mut Stats) {
+        stats.counter("issued", self.issued);
+        stats.counter("useful", self.useful);
+        stats.counter("late", self.late);
+        stats.counter("dropped", self.dropped);
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+struct StrideEntry {
+    valid: bool,
+    pc: usize,
+    last_addr: usize,
+    stride: isize,
+    confidence: u32,
+}
+
+const STRIDE_CONFIDENT: u32 = 2;
+const STRIDE_MAX_CONFIDENCE: u32 = 3;
+
+#[derive(Debug, Clone)]
+pub struct Prefetcher {
+    pub kind: PrefetcherKind,
+    degree: usize,
+    stride_table: Vec<StrideEntry>,
+    // Lines held by the stream buffer with the cycle each arrives
+    stream: VecDeque<(usize, u64)>,
+    pub stats: PrefetchStats,
+}
+
+impl Prefetcher {
+    pub fn new(kind: PrefetcherKind, degree: usize, table_size: usize) -> Prefetcher {
+        if degree == 0 || table_size == 0 {
+            panic!("Prefetch degree and stride table size must be non zero");
+        }
+        Prefetcher {
+            kind,
+            degree,
+            stride_table: vec![StrideEntry { valid: false, pc: 0, last_addr: 0, stride: 0, confidence: 0 }; table_size],
+            stream: VecDeque::new(),
+            stats: PrefetchStats::default(),
+        }
+    }
+
+    // Lines worth bringing into the cache after a demand access
+    pub fn observe(&mut self, pc: usize, addr: usize, line_size: usize, miss: bool, prefetch_hit: bool) -> Vec<usize> {
+        let line = addr / line_size;
+        match self.kind {
+            PrefetcherKind::NextLine => {
+                // Tagged next line prefetching also continues on the first use of a prefetched line
+                if miss || prefetch_hit {
+                    (1..self.degree + 1).map(|d| line + d).collect()
+                } else {
+                    Vec::new()
+                }
+            },
+            PrefetcherKind::Stride => {
+                let index = pc % self.stride_table.len();
+                let entry = &mut self.stride_table[index];
+                if !entry.valid || entry.pc != pc {
+                    *entry = StrideEntry { valid: true, pc, last_addr: addr, stride: 0, confidence: 0 };
+                    return Vec::new();
+                }
+                let stride = addr as isize - entry.last_addr as isize;
+                if stride == entry.stride && stride != 0 {
+                    if entry.confidence < STRIDE_MAX_CONFIDENCE {
+                        entry.confidence += 1;
+                    }
+                } else {
+                    entry.confidence = 0;
+                    entry.stride = stride;
+                }
+                entry.last_addr = addr;
+                if entry.confidence < STRIDE_CONFIDENT {
+                    return Vec::new();
+                }
+                let mut lines = Vec::new();
+                for d in 1..self.degree as isize + 1 {
+                    let target = addr as isize + entry.stride * d;
+                    if target < 0 {
+                        break;
+                    }
+                    let target_line = target as usize / line_size;
+                    if target_line != line && !lines.contains(&target_line) {
+                        lines.push(target_line);
+                    }
+                }
+                lines
+            },
+            PrefetcherKind::None | PrefetcherKind::Stream => Vec::new(),
+        }
+    }
+
+    // Takes a line out of the stream buffer, returning when it arrives
+    pub fn stream_take(&mut self, line: usize) -> Option<u64> {
+        let position = self.stream.iter().position(|&(l, _)| l == line)?;
+        // Entries ahead of the line are skipped over and discarded
+        let mut ready = 0;
+        for _ in 0..position + 1 {
+            ready = self.stream.pop_front().unwrap().1;
+        }
+        Some(ready)
+    }
+
+    // Lines to fetch so the stream buffer stays full after it has been used
+    pub fn stream_refill(&self, line: usize) -> Vec<usize> {
+        let tail = self.stream.back().map_or(line, |&(l, _)| l);
+        let missing = self.degree - self.stream.len();
+        (1..missing + 1).map(|d| tail + d).collect()
+    }
+
+    // Starts a new stream after a miss in both the cache and the stream buffer
+    pub fn stream_restart(&mut self, line: usize) -> Vec<usize> {
+        self.stream.clear();
+        (1..self.degree + 1).map(|d| line + d).collect()
+    }
+
+    pub fn stream_push(&mut self, line: usize, ready: u64) {
+        self.stream.push_back((line, ready));
+    }
+}
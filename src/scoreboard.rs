@@ -0,0 +1,545 @@
+use std::fmt;
+use hierarchy::MemorySystem;
+use stats::{self, Stats};
+use super::{compute, BranchPredictor, Core, DecodedInstruction, EncodedInstruction, ExecResult, FUConfig, FUType, InstructionKind, Op, Source, MEM_SIZE};
+
+// Dynamic scheduling without renaming after the CDC 6600. Instructions issue in order to a free unit
+// once no other unit is due to write their destination, read operands once every producer has written,
+// and write their result once no unit still has to read the old value.
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum UnitKind {
+    Fu(FUType),
+    // A single load/store unit keeps memory accesses in program order
+    Memory,
+}
+
+#[derive(Debug, Copy, Clone)]
+struct InstructionStatus {
+    seq: u64,
+    pc: usize,
+    instruction: EncodedInstruction,
+    issue: u64,
+    read: Option<u64>,
+    complete: Option<u64>,
+    write: Option<u64>,
+}
+
+// A row of the functional unit status table
+#[derive(Debug, Clone)]
+struct Unit {
+    name: String,
+    kind: UnitKind,
+    busy: bool,
+    decoded: Option<DecodedInstruction>,
+    fi: Option<usize>,
+    fj: Option<usize>,
+    fk: Option<usize>,
+    // Units that will produce the sources
+    qj: Option<usize>,
+    qk: Option<usize>,
+    // Sources that are ready but have not been read yet
+    rj: bool,
+    rk: bool,
+    seq: u64,
+    pc: usize,
+    predicted: usize,
+    vj: u32,
+    vk: u32,
+    result: u32,
+    remaining: u32,
+    access_pending: bool,
+    busy_cycles: u64,
+}
+
+impl Unit {
+    fn new(name: String, kind: UnitKind) -> Unit {
+        Unit {
+            name,
+            kind,
+            busy: false,
+            decoded: None,
+            fi: None,
+            fj: None,
+            fk: None,
+            qj: None,
+            qk: None,
+            rj: false,
+            rk: false,
+            seq: 0,
+            pc: 0,
+            predicted: 0,
+            vj: 0,
+            vk: 0,
+            result: 0,
+            remaining: 0,
+            access_pending: false,
+            busy_cycles: 0,
+        }
+    }
+
+    fn release(&mut self) {
+        self.busy = false;
+        self.decoded = None;
+        self.fi = None;
+        self.fj = None;
+        self.fk = None;
+        self.qj = None;
+        self.qk = None;
+        self.rj = false;
+        self.rk = false;
+    }
+
+    fn op_name(&self) -> String {
+        match self.decoded {
+            Some(DecodedInstruction { kind: InstructionKind::Load, .. }) => "Load".to_string(),
+            Some(DecodedInstruction { kind: InstructionKind::Store, .. }) => "Store".to_string(),
+            Some(decoded) => format!("{:?}", decoded.op),
+            None => String::new(),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ScoreboardStats {
+    pub retired: u64,
+    // Cycles issue was held back, by cause
+    pub structural_stall_cycles: u64,
+    pub waw_stall_cycles: u64,
+    pub branch_stall_cycles: u64,
+    pub empty_cycles: u64,
+    // Summed over units, cycles spent waiting to read operands or to write a result
+    pub raw_stall_cycles: u64,
+    pub war_stall_cycles: u64,
+    pub mem_stall_cycles: u64,
+    pub flushes: u64,
+    pub flushed_instructions: u64,
+    pub icache_stall_cycles: u64,
+}
+
+impl ScoreboardStats {
+    fn record(&self, stats: &mut Stats) {
+        stats.counter("instructions", self.retired);
+        stats.counter("structural_stall_cycles", self.structural_stall_cycles);
+        stats.counter("waw_stall_cycles", self.waw_stall_cycles);
+        stats.counter("branch_stall_cycles", self.branch_stall_cycles);
+        stats.counter("empty_cycles", self.empty_cycles);
+        stats.counter("raw_stall_cycles", self.raw_stall_cycles);
+        stats.counter("war_stall_cycles", self.war_stall_cycles);
+        stats.counter("mem_stall_cycles", self.mem_stall_cycles);
+        stats.counter("flushes", self.flushes);
+        stats.counter("flushed_instructions", self.flushed_instructions);
+        stats.counter("icache_stall_cycles", self.icache_stall_cycles);
+    }
+}
+
+pub struct Scoreboard {
+    instructions: Vec<EncodedInstruction>,
+    pc: usize,
+    fetch_width: usize,
+    fetch_stall: u32,
+    filled_line: Option<usize>,
+    buffer: Vec<(usize, EncodedInstruction, DecodedInstruction, usize)>,
+    capacity: usize,
+    latencies: Vec<(Op, FUType, u32)>,
+    units: Vec<Unit>,
+    // The unit that will write each register
+    register_status: [Option<usize>; 32],
+    window: Vec<InstructionStatus>,
+    branch_pending: bool,
+    cycle: u64,
+    seq: u64,
+    registers: [u32; 32],
+    pub branch_predictor: BranchPredictor,
+    pub stats: ScoreboardStats,
+}
+
+impl Scoreboard {
+    pub fn new(instructions: Vec<EncodedInstruction>, pred_type: usize, fetch_width: usize, capacity: usize, fu_pool: &[FUConfig]) -> Scoreboard {
+        if fetch_width == 0 || capacity == 0 {
+            panic!("The scoreboard needs a fetch width and instruction buffer of at least one");
+        }
+        let mut latencies = Vec::new();
+        let mut units = Vec::new();
+        for config in fu_pool {
+            for &(op, latency) in &config.latencies {
+                latencies.push((op, config.fu_type, latency));
+            }
+            let existing = units.iter().filter(|u: &&Unit| u.kind == UnitKind::Fu(config.fu_type)).count();
+            for i in 0..config.count {
+                units.push(Unit::new(format!("{:?}{}", config.fu_type, existing + i), UnitKind::Fu(config.fu_type)));
+            }
+        }
+        units.push(Unit::new("Memory".to_string(), UnitKind::Memory));
+        Scoreboard {
+            instructions,
+            pc: 0,
+            fetch_width,
+            fetch_stall: 0,
+            filled_line: None,
+            buffer: Vec::new(),
+            capacity,
+            latencies,
+            units,
+            register_status: [None; 32],
+            window: Vec::new(),
+            branch_pending: false,
+            cycle: 0,
+            seq: 0,
+            registers: [0; 32],
+            branch_predictor: BranchPredictor::new(pred_type),
+            stats: Default::default(),
+        }
+    }
+
+    pub fn unit_busy_cycles(&self) -> Vec<(String, u64)> {
+        self.units.iter().map(|u| (u.name.clone(), u.busy_cycles)).collect()
+    }
+
+    fn unit_kind(&self, decoded: &DecodedInstruction) -> UnitKind {
+        if decoded.is_memory() {
+            return UnitKind::Memory;
+        }
+        match self.latencies.iter().find(|&&(op, _, _)| op == decoded.op) {
+            Some(&(_, fu_type, _)) => UnitKind::Fu(fu_type),
+            None => panic!("No functional unit can execute {:?}", decoded.op),
+        }
+    }
+
+    fn latency(&self, op: Op) -> u32 {
+        self.latencies.iter().find(|&&(o, _, _)| o == op).map(|&(_, _, latency)| latency).unwrap()
+    }
+
+    fn status(&self, seq: u64) -> &InstructionStatus {
+        self.window.iter().find(|s| s.seq == seq).unwrap()
+    }
+
+    fn status_mut(&mut self, seq: u64) -> &mut InstructionStatus {
+        self.window.iter_mut().find(|s| s.seq == seq).unwrap()
+    }
+
+    fn issue(&mut self) {
+        let (pc, instruction, decoded, predicted) = match self.buffer.first() {
+            Some(&entry) => entry,
+            None => {
+                self.stats.empty_cycles += 1;
+                return;
+            },
+        };
+        if self.branch_pending {
+            self.stats.branch_stall_cycles += 1;
+            return;
+        }
+        let kind = self.unit_kind(&decoded);
+        let unit = match self.units.iter().position(|u| !u.busy && u.kind == kind) {
+            Some(unit) => unit,
+            None => {
+                self.stats.structural_stall_cycles += 1;
+                return;
+            },
+        };
+        if let Some(d) = decoded.dest {
+            if self.register_status[d].is_some() {
+                self.stats.waw_stall_cycles += 1;
+                return;
+            }
+        }
+        self.buffer.remove(0);
+
+        let mut sources = [(None, None, false, 0); 2];
+        for (i, source) in decoded.sources.iter().enumerate() {
+            sources[i] = match *source {
+                Source::Reg(r) => (Some(r), self.register_status[r], self.register_status[r].is_none(), 0),
+                Source::Imm(imm) => (None, None, false, imm),
+            };
+        }
+        self.seq += 1;
+        {
+            let u = &mut self.units[unit];
+            u.busy = true;
+            u.decoded = Some(decoded);
+            u.fi = decoded.dest;
+            u.fj = sources[0].0;
+            u.qj = sources[0].1;
+            u.rj = sources[0].2;
+            u.vj = sources[0].3;
+            u.fk = sources[1].0;
+            u.qk = sources[1].1;
+            u.rk = sources[1].2;
+            u.vk = sources[1].3;
+            u.seq = self.seq;
+            u.pc = pc;
+            u.predicted = predicted;
+        }
+        if let Some(d) = decoded.dest {
+            self.register_status[d] = Some(unit);
+        }
+        if decoded.kind == InstructionKind::Branch {
+            self.branch_pending = true;
+        }
+        self.window.push(InstructionStatus {
+            seq: self.seq,
+            pc,
+            instruction,
+            issue: self.cycle,
+            read: None,
+            complete: None,
+            write: None,
+        });
+    }
+
+    fn read_operands(&mut self) {
+        for unit in 0..self.units.len() {
+            let (seq, read) = {
+                let u = &self.units[unit];
+                if !u.busy || u.remaining > 0 || u.access_pending {
+                    continue;
+                }
+                let status = self.status(u.seq);
+                if status.issue == self.cycle || status.read.is_some() {
+                    continue;
+                }
+                (u.seq, u.qj.is_none() && u.qk.is_none())
+            };
+            if !read {
+                self.stats.raw_stall_cycles += 1;
+                continue;
+            }
+            let decoded = self.units[unit].decoded.unwrap();
+            let latency = if decoded.is_memory() { 0 } else { self.latency(decoded.op) };
+            let registers = self.registers;
+            let u = &mut self.units[unit];
+            if let Some(j) = u.fj {
+                u.vj = registers[j];
+            }
+            if let Some(k) = u.fk {
+                u.vk = registers[k];
+            }
+            u.rj = false;
+            u.rk = false;
+            u.remaining = latency;
+            u.access_pending = decoded.is_memory();
+            let cycle = self.cycle;
+            self.status_mut(seq).read = Some(cycle);
+        }
+    }
+
+    fn execute(&mut self, memory: &mut [u32; MEM_SIZE], mem_system: &mut MemorySystem) {
+        for unit in 0..self.units.len() {
+            let seq = self.units[unit].seq;
+            let started = {
+                let u = &self.units[unit];
+                u.busy && (u.remaining > 0 || u.access_pending)
+            };
+            if !started || self.status(seq).read == Some(self.cycle) {
+                continue;
+            }
+            let decoded = self.units[unit].decoded.unwrap();
+            if self.units[unit].access_pending {
+                let u = &mut self.units[unit];
+                let addr = u.vj as usize;
+                let write = decoded.kind == InstructionKind::Store;
+                match mem_system.access_data(0, addr, write, u.pc) {
+                    Some(latency) => {
+                        if write {
+                            memory[addr] = u.vk;
+                        } else {
+                            u.result = memory[addr];
+                        }
+                        u.remaining = if latency == 0 { 1 } else { latency };
+                        u.access_pending = false;
+                    },
+                    None => {
+                        self.stats.mem_stall_cycles += 1;
+                        continue;
+                    },
+                }
+            }
+            self.units[unit].remaining -= 1;
+            if self.units[unit].remaining > 0 {
+                continue;
+            }
+
+            let cycle = self.cycle;
+            self.status_mut(seq).complete = Some(cycle);
+            if decoded.is_memory() {
+                continue;
+            }
+            let (vj, vk, pc, predicted) = {
+                let u = &self.units[unit];
+                (u.vj, u.vk, u.pc, u.predicted)
+            };
+            match compute(decoded.op, vj, vk, decoded.target) {
+                ExecResult::Value(value) => self.units[unit].result = value,
+                ExecResult::BranchTaken(_) => self.resolve(pc, decoded.target, predicted),
+                ExecResult::BranchNotTaken() => self.resolve(pc, pc + 1, predicted),
+                ExecResult::Store => panic!("Computed a store"),
+            }
+        }
+    }
+
+    // Nothing issues past an unresolved branch so only the fetched instructions need discarding
+    fn resolve(&mut self, pc: usize, taken_pc: usize, predicted: usize) {
+        let correct = self.branch_predictor.prediction_correct(predicted, taken_pc, pc);
+        self.branch_pending = false;
+        if !correct {
+            self.stats.flushes += 1;
+            self.stats.flushed_instructions += self.buffer.len() as u64;
+            self.buffer.clear();
+            self.pc = taken_pc;
+            self.fetch_stall = 0;
+            self.filled_line = None;
+        }
+    }
+
+    fn write_result(&mut self) {
+        for unit in 0..self.units.len() {
+            if !self.units[unit].busy {
+                continue;
+            }
+            let seq = self.units[unit].seq;
+            match self.status(seq).complete {
+                Some(cycle) if cycle < self.cycle => (),
+                _ => continue,
+            }
+            if let Some(d) = self.units[unit].fi {
+                let war = self.units.iter().any(|u| (u.fj == Some(d) && u.rj) || (u.fk == Some(d) && u.rk));
+                if war {
+                    self.stats.war_stall_cycles += 1;
+                    continue;
+                }
+                self.registers[d] = self.units[unit].result;
+                self.register_status[d] = None;
+                for u in &mut self.units {
+                    if u.qj == Some(unit) {
+                        u.qj = None;
+                        u.rj = true;
+                    }
+                    if u.qk == Some(unit) {
+                        u.qk = None;
+                        u.rk = true;
+                    }
+                }
+            }
+            self.units[unit].release();
+            let cycle = self.cycle;
+            self.status_mut(seq).write = Some(cycle);
+            self.stats.retired += 1;
+        }
+    }
+
+    fn fetch(&mut self, mem_system: &mut MemorySystem) {
+        if self.fetch_stall > 0 {
+            self.fetch_stall -= 1;
+            self.stats.icache_stall_cycles += 1;
+            return;
+        }
+        if self.pc >= self.instructions.len() || self.buffer.len() == self.capacity {
+            return;
+        }
+
+        //Fetch blocks are line aligned so one instruction cache access covers the block
+        let line_size = mem_system.l1i_line_size();
+        let line = self.pc / line_size;
+        if self.filled_line != Some(line) {
+            let stall = mem_system.access_instruction(0, self.pc);
+            if stall > 0 {
+                self.fetch_stall = stall - 1;
+                self.stats.icache_stall_cycles += 1;
+                self.filled_line = Some(line);
+                return;
+            }
+        }
+        self.filled_line = None;
+
+        for _ in 0..self.fetch_width {
+            if self.pc >= self.instructions.len() || self.buffer.len() == self.capacity {
+                break;
+            }
+            let pc = self.pc;
+            let instruction = self.instructions[pc];
+            let next = match instruction {
+                EncodedInstruction::J(inst) => inst,
+                EncodedInstruction::Beq(_, _, _) |
+                EncodedInstruction::Beqz(_, _) |
+                EncodedInstruction::Blt(_, _, _) |
+                EncodedInstruction::Bgt(_, _, _) => self.branch_predictor.predict(instruction, pc),
+                _ => pc + 1,
+            };
+            if let Some(decoded) = DecodedInstruction::new(instruction) {
+                self.buffer.push((pc, instruction, decoded, next));
+            }
+            self.pc = next;
+            if next != pc + 1 || self.pc.is_multiple_of(line_size) {
+                break;
+            }
+        }
+    }
+}
+
+impl Core for Scoreboard {
+    // Issue runs first so a unit or register freed this cycle is only reused the next,
+    // and each instruction moves through at most one step per cycle
+    fn cycle(&mut self, memory: &mut [u32; MEM_SIZE], mem_system: &mut MemorySystem) {
+        self.cycle += 1;
+        let cycle = self.cycle;
+        self.window.retain(|s| s.write.is_none_or(|w| w >= cycle));
+        for u in &mut self.units {
+            if u.busy {
+                u.busy_cycles += 1;
+            }
+        }
+
+        self.issue();
+        self.read_operands();
+        self.execute(memory, mem_system);
+        self.write_result();
+        self.fetch(mem_system);
+    }
+
+    fn finished(&self) -> bool {
+        self.pc >= self.instructions.len() && self.buffer.is_empty() && self.units.iter().all(|u| !u.busy)
+    }
+
+    fn registers(&self) -> &[u32; 32] {
+        &self.registers
+    }
+
+    fn record(&self, stats: &mut Stats) {
+        self.stats.record(stats);
+        stats.enter("branch_predictor");
+        self.branch_predictor.record(stats);
+        stats.leave();
+        for u in &self.units {
+            stats.enter(&stats::key(&u.name));
+            stats.counter("busy_cycles", u.busy_cycles);
+            stats.leave();
+        }
+    }
+}
+
+// The instruction status, functional unit status and register result status tables
+impl fmt::Debug for Scoreboard {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let cycle = |c: Option<u64>| c.map_or(String::new(), |c| c.to_string());
+        let reg = |r: Option<usize>| r.map_or(String::new(), |r| format!("R{}", r));
+        let unit = |u: Option<usize>| u.map_or(String::new(), |u| self.units[u].name.clone());
+        let flag = |set: bool| if set { "Yes" } else { "No" };
+        let ready = |u: &Unit, set: bool| if u.busy { flag(set) } else { "" };
+
+        writeln!(f, "Cycle {}", self.cycle)?;
+        writeln!(f, "{:<28} {:>6} {:>6} {:>9} {:>6}", "Instruction", "Issue", "Read", "Complete", "Write")?;
+        for s in &self.window {
+            let name = format!("{}: {:?}", s.pc, s.instruction);
+            writeln!(f, "{:<28} {:>6} {:>6} {:>9} {:>6}", name, s.issue, cycle(s.read), cycle(s.complete), cycle(s.write))?;
+        }
+        writeln!(f, "{:<12} {:<5} {:<6} {:<4} {:<4} {:<4} {:<12} {:<12} {:<4} Rk", "Unit", "Busy", "Op", "Fi", "Fj", "Fk", "Qj", "Qk", "Rj")?;
+        for u in &self.units {
+            writeln!(f, "{:<12} {:<5} {:<6} {:<4} {:<4} {:<4} {:<12} {:<12} {:<4} {}", u.name, flag(u.busy), u.op_name(), reg(u.fi), reg(u.fj), reg(u.fk), unit(u.qj), unit(u.qk), ready(u, u.rj), ready(u, u.rk))?;
+        }
+        let pending: Vec<String> = self.register_status.iter().enumerate()
+            .filter_map(|(r, u)| u.map(|u| format!("R{}: {}", r, self.units[u].name)))
+            .collect();
+        write!(f, "Register result status: {}", pending.join("  "))
+    }
+}
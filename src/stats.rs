@@ -0,0 +1,115 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+#[derive(Debug, Clone)]
+pub enum Stat {
+    Counter(u64),
+    // Counts by bucket, such as cycles spent with each number of entries in use
+    Histogram(Vec<u64>),
+    // Final contents of registers or memory
+    Values(Vec<u32>),
+}
+
+// Counters from every unit gathered under dotted names such as "core0.rob.stall_cycles", so runs can be
+// written out as JSON or CSV rather than read from the printed report. Units record their counters under
+// whatever scope they are given, the whole set being recorded again for each snapshot of the run.
+pub struct Stats {
+    // Cycles between snapshots taken during the run, only the end of the run being recorded when None
+    interval: Option<u64>,
+    scope: Vec<String>,
+    recording: Vec<(String, Stat)>,
+    snapshots: Vec<(u64, Vec<(String, Stat)>)>,
+}
+
+// Turns a unit's display name into a name part, "Core 0 L1D" becoming "core_0_l1d"
+pub fn key(name: &str) -> String {
+    name.to_lowercase().replace(' ', "_")
+}
+
+impl Stats {
+    pub fn new(interval: Option<u64>) -> Stats {
+        if interval == Some(0) {
+            panic!("Statistics interval must be at least one cycle");
+        }
+        Stats {
+            interval,
+            scope: Vec::new(),
+            recording: Vec::new(),
+            snapshots: Vec::new(),
+        }
+    }
+
+    pub fn due(&self, cycle: u64) -> bool {
+        self.interval.is_some_and(|interval| cycle.is_multiple_of(interval))
+    }
+
+    pub fn enter(&mut self, scope: &str) {
+        self.scope.push(scope.to_string());
+    }
+
+    pub fn leave(&mut self) {
+        self.scope.pop();
+    }
+
+    fn record(&mut self, name: &str, stat: Stat) {
+        let mut parts = self.scope.clone();
+        parts.push(name.to_string());
+        self.recording.push((parts.join("."), stat));
+    }
+
+    pub fn counter(&mut self, name: &str, value: u64) {
+        self.record(name, Stat::Counter(value));
+    }
+
+    pub fn histogram(&mut self, name: &str, buckets: &[u64]) {
+        self.record(name, Stat::Histogram(buckets.to_vec()));
+    }
+
+    pub fn values(&mut self, name: &str, values: &[u32]) {
+        self.record(name, Stat::Values(values.to_vec()));
+    }
+
+    // Files away everything recorded since the last snapshot as the state at the given cycle
+    pub fn snapshot(&mut self, cycle: u64) {
+        let recorded = self.recording.drain(..).collect();
+        self.snapshots.push((cycle, recorded));
+    }
+
+    // The last snapshot is the end of the run and the rest were taken at intervals along the way
+    pub fn write_json(&self, path: &str) {
+        let snapshot = |&(cycle, ref recorded): &(u64, Vec<(String, Stat)>)| {
+            let stats: Vec<String> = recorded.iter().map(|(name, stat)| {
+                let value = match *stat {
+                    Stat::Counter(count) => count.to_string(),
+                    Stat::Histogram(ref buckets) => format!("[{}]", buckets.iter().map(|b| b.to_string()).collect::<Vec<String>>().join(", ")),
+                    Stat::Values(ref values) => format!("[{}]", values.iter().map(|v| v.to_string()).collect::<Vec<String>>().join(", ")),
+                };
+                format!("\"{}\": {}", name, value)
+            }).collect();
+            format!("{{\"cycle\": {}, \"stats\": {{\n{}\n}}}}", cycle, stats.join(",\n"))
+        };
+        let (last, intervals) = self.snapshots.split_last().expect("No statistics recorded");
+        let intervals: Vec<String> = intervals.iter().map(&snapshot).collect();
+        let mut out = BufWriter::new(File::create(path).unwrap());
+        writeln!(out, "{{\"intervals\": [{}],\n\"final\": {}}}", intervals.join(",\n"), snapshot(last)).unwrap();
+    }
+
+    // One row per value, histogram buckets and register or memory words numbered after their name
+    pub fn write_csv(&self, path: &str) {
+        let mut out = BufWriter::new(File::create(path).unwrap());
+        writeln!(out, "cycle,name,value").unwrap();
+        for &(cycle, ref recorded) in &self.snapshots {
+            for (name, stat) in recorded {
+                match *stat {
+                    Stat::Counter(count) => writeln!(out, "{},{},{}", cycle, name, count).unwrap(),
+                    Stat::Histogram(ref buckets) => for (i, count) in buckets.iter().enumerate() {
+                        writeln!(out, "{},{}.{},{}", cycle, name, i, count).unwrap();
+                    },
+                    Stat::Values(ref values) => for (i, value) in values.iter().enumerate() {
+                        writeln!(out, "{},{}.{},{}", cycle, name, i, value).unwrap();
+                    },
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,81 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+// A row of the timeline showing what one unit was doing
+struct Track {
+    name: String,
+    // What the unit has been doing since the given cycle, consecutive cycles doing the same thing making one slice
+    current: Option<(String, u64)>,
+}
+
+// Activity of one core's units over a run as events in the Chrome Trace Event format, which
+// chrome://tracing and Perfetto open. Timestamps in the format are microseconds so a cycle shows as one.
+pub struct Timeline {
+    core: usize,
+    pub cycle: u64,
+    tracks: Vec<Track>,
+    // ROB entries each thread held when last recorded
+    occupancy: Vec<usize>,
+    events: Vec<String>,
+}
+
+impl Timeline {
+    pub fn new(core: usize, tracks: Vec<String>) -> Timeline {
+        Timeline {
+            core,
+            cycle: 0,
+            tracks: tracks.into_iter().map(|name| Track { name, current: None }).collect(),
+            occupancy: Vec::new(),
+            events: Vec::new(),
+        }
+    }
+
+    // Records what a unit is doing this cycle, None when it sits idle
+    pub fn activity(&mut self, track: usize, activity: Option<String>) {
+        if self.tracks[track].current.as_ref().map(|current| &current.0) != activity.as_ref() {
+            self.close(track);
+            self.tracks[track].current = activity.map(|activity| (activity, self.cycle));
+        }
+    }
+
+    fn close(&mut self, track: usize) {
+        if let Some((name, start)) = self.tracks[track].current.take() {
+            self.events.push(format!("{{\"name\": \"{}\", \"ph\": \"X\", \"ts\": {}, \"dur\": {}, \"pid\": {}, \"tid\": {}}}",
+                                     name, start, self.cycle - start, self.core, track + 1));
+        }
+    }
+
+    // Records how many ROB entries each thread holds, only when that changes
+    pub fn occupancy(&mut self, occupancy: &[usize]) {
+        if occupancy != &self.occupancy[..] {
+            let threads: Vec<String> = occupancy.iter().enumerate().map(|(t, entries)| format!("\"thread {}\": {}", t, entries)).collect();
+            self.events.push(format!("{{\"name\": \"ROB occupancy\", \"ph\": \"C\", \"ts\": {}, \"pid\": {}, \"args\": {{{}}}}}",
+                                     self.cycle, self.core, threads.join(", ")));
+            self.occupancy = occupancy.to_vec();
+        }
+    }
+
+    // Names the core and its tracks, which sort in the order given
+    fn metadata(&self) -> Vec<String> {
+        let mut events = vec![format!("{{\"name\": \"process_name\", \"ph\": \"M\", \"pid\": {}, \"args\": {{\"name\": \"Core {}\"}}}}", self.core, self.core)];
+        for (t, track) in self.tracks.iter().enumerate() {
+            events.push(format!("{{\"name\": \"thread_name\", \"ph\": \"M\", \"pid\": {}, \"tid\": {}, \"args\": {{\"name\": \"{}\"}}}}", self.core, t + 1, track.name));
+            events.push(format!("{{\"name\": \"thread_sort_index\", \"ph\": \"M\", \"pid\": {}, \"tid\": {}, \"args\": {{\"sort_index\": {}}}}}", self.core, t + 1, t + 1));
+        }
+        events
+    }
+}
+
+// Writes the timelines of every core to one file, ending slices still open where each core stopped
+pub fn write(path: &str, mut timelines: Vec<Timeline>) {
+    let mut events = Vec::new();
+    for timeline in &mut timelines {
+        for track in 0..timeline.tracks.len() {
+            timeline.close(track);
+        }
+        events.extend(timeline.metadata());
+        events.append(&mut timeline.events);
+    }
+    let mut out = BufWriter::new(File::create(path).unwrap());
+    writeln!(out, "{{\"traceEvents\": [\n{}\n]}}", events.join(",\n")).unwrap();
+}